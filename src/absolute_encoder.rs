@@ -0,0 +1,326 @@
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+#![allow(non_snake_case)]
+
+//! absolute encoder frame decoding
+//!
+//! Absolute encoders tell us the rotor angle right after power up, no index pulse or alignment
+//! needed. They hand it over in a serial frame, and every protocol packs it differently. The
+//! functions here decode the raw frames, as they come out of the SPI or SSI peripheral, into a
+//! [Reading]. Clocking the bits in is left to you and your hardware.
+//!
+//! Frames are expected right aligned, with the last received bit as least significant bit.
+//!
+//! - SSI: just the position, either binary or gray coded
+//! - BiSS-C: position, active low error and warning bits, and an inverted CRC6 with polynomial
+//!   x^6 + x + 1
+//! - ams AS5047/AS5048 over SPI: even parity bit, error flag and 14 bit angle
+//! - MPS MagAlpha over SPI: 16 bit frame with the angle left aligned
+//!
+//! [AbsoluteEncoder] turns the readings into a [Mechanical] state with a configurable zero.
+
+use crate::motor::{wrap_angle, wrap_angle_diff, Mechanical};
+use core::f32::consts::PI;
+
+/// absolute encoder frame errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// parity bit doesn't match the frame
+    Parity,
+    /// CRC doesn't match the frame
+    Crc,
+    /// encoder signals an error in its frame
+    EncoderError,
+    /// position width is 0 or doesn't fit into the frame or a u32 position
+    InvalidBits,
+}
+
+/// decoded position of an absolute encoder
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Reading {
+    /// position in counts from 0 to 2^resolution_bits
+    pub position: u32,
+    /// resolution of the position in bits, up to 32
+    pub resolution_bits: u8,
+    /// encoder signals a warning, position is still valid
+    pub warning: bool,
+}
+
+impl Reading {
+    /// angle in rad from 0 to 2π, shifted by zero offset in rad
+    pub fn angle(&self, zero_offset: f32) -> f32 {
+        let counts = (1u64 << self.resolution_bits.min(32)) as f32;
+        let angle = self.position as f32 / counts * 2f32 * PI - zero_offset;
+        wrap_angle(angle)
+    }
+}
+
+/// decode binary SSI frame with given position bits, up to 32
+pub fn ssi_binary(frame: u32, bits: u8) -> Result<Reading, Error> {
+    check_bits(bits, 32)?;
+    Ok(Reading {
+        position: frame & mask(bits),
+        resolution_bits: bits,
+        warning: false,
+    })
+}
+
+/// decode gray coded SSI frame with given position bits, up to 32
+pub fn ssi_gray(frame: u32, bits: u8) -> Result<Reading, Error> {
+    check_bits(bits, 32)?;
+    let mut position = frame & mask(bits);
+    let mut shift = position >> 1;
+    while shift != 0 {
+        position ^= shift;
+        shift >>= 1;
+    }
+    Ok(Reading {
+        position,
+        resolution_bits: bits,
+        warning: false,
+    })
+}
+
+/// decode BiSS-C single cycle data: position bits up to 32, error bit, warning bit and CRC6
+pub fn biss_c(frame: u64, bits: u8) -> Result<Reading, Error> {
+    check_bits(bits, 32)?;
+    let crc = (frame & 0x3F) as u8;
+    let data = (frame >> 6) & ((1u64 << (bits + 2)) - 1);
+    if biss_crc6(data, bits + 2) != crc {
+        return Err(Error::Crc);
+    }
+    // error and warning bits are active low
+    if data & 0b10 == 0 {
+        return Err(Error::EncoderError);
+    }
+    Ok(Reading {
+        position: (data >> 2) as u32,
+        resolution_bits: bits,
+        warning: data & 0b01 == 0,
+    })
+}
+
+/// inverted CRC6 of BiSS-C over given number of data bits, polynomial x^6 + x + 1. Bits beyond
+/// the 64 of data count as 0.
+pub fn biss_crc6(data: u64, bits: u8) -> u8 {
+    let mut crc = 0u8;
+    for i in (0..bits).rev() {
+        let bit = (data.checked_shr(i as u32).unwrap_or(0) & 1) as u8;
+        let feedback = ((crc >> 5) & 1) ^ bit;
+        crc = (crc << 1) & 0x3F;
+        if feedback == 1 {
+            crc ^= 0x03;
+        }
+    }
+    !crc & 0x3F
+}
+
+/// decode ams AS5047/AS5048 angle frame: even parity on bit 15, error flag on bit 14 and 14 bit
+/// angle
+pub fn as504x(frame: u16) -> Result<Reading, Error> {
+    if frame.count_ones() & 1 != 0 {
+        return Err(Error::Parity);
+    }
+    if frame & 0x4000 != 0 {
+        return Err(Error::EncoderError);
+    }
+    Ok(Reading {
+        position: (frame & 0x3FFF) as u32,
+        resolution_bits: 14,
+        warning: false,
+    })
+}
+
+/// decode MPS MagAlpha angle frame with the angle left aligned in 16 bits, up to 16 position bits
+pub fn magalpha(frame: u16, bits: u8) -> Result<Reading, Error> {
+    check_bits(bits, 16)?;
+    Ok(Reading {
+        position: (frame >> (16 - bits)) as u32,
+        resolution_bits: bits,
+        warning: false,
+    })
+}
+
+/// check position width for 1 up to bits_max
+fn check_bits(bits: u8, bits_max: u8) -> Result<(), Error> {
+    if bits == 0 || bits > bits_max {
+        return Err(Error::InvalidBits);
+    }
+    Ok(())
+}
+
+/// mask for the lower bits, up to 32
+fn mask(bits: u8) -> u32 {
+    u32::MAX >> (32 - bits.min(32))
+}
+
+/// absolute encoder rotor state estimation struct
+#[derive(PartialEq, Debug)]
+pub struct AbsoluteEncoder {
+    /// encoder angle in rad that marks the rotor's zero
+    zero_offset: f32,
+    /// most recent mechanical state
+    state: Mechanical,
+    /// sample time
+    t_sample: f32,
+}
+
+impl AbsoluteEncoder {
+    /// create new absolute encoder with zero offset in rad
+    pub fn new(zero_offset: f32, f_sample_Hz: f32) -> AbsoluteEncoder {
+        AbsoluteEncoder {
+            zero_offset,
+            state: Mechanical {
+                angle: 0f32,
+                speed: 0f32,
+                acceleration: 0f32,
+            },
+            t_sample: 1f32 / f_sample_Hz,
+        }
+    }
+
+    /// change zero offset in rad
+    pub fn set_zero_offset(&mut self, zero_offset: f32) {
+        self.zero_offset = zero_offset;
+    }
+
+    /// run this method once per sample period with a decoded reading. Returns the mechanical
+    /// rotor state.
+    pub fn update(&mut self, reading: &Reading) -> Mechanical {
+        let angle = reading.angle(self.zero_offset);
+        let speed = wrap_angle_diff(angle - self.state.angle) / self.t_sample;
+
+        self.state = Mechanical {
+            angle,
+            speed,
+            acceleration: (speed - self.state.speed) / self.t_sample,
+        };
+
+        Mechanical { ..self.state }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ssi() {
+        let reading = ssi_binary(0xF_1234, 16).unwrap();
+        assert_eq!(reading.position, 0x1234);
+
+        // gray code of 0b1011 is 0b1110
+        let reading = ssi_gray(0b1110, 4).unwrap();
+        assert_eq!(reading.position, 0b1011);
+        let reading = ssi_gray(0b1000_0000_0000, 12).unwrap();
+        assert_eq!(reading.position, 0b1111_1111_1111);
+
+        // a quarter turn
+        let reading = ssi_binary(1 << 10, 12).unwrap();
+        assert!(float_cmp::approx_eq!(
+            f32,
+            reading.angle(0f32),
+            PI / 2f32,
+            epsilon = 1e-5
+        ));
+        // with zero offset
+        assert!(float_cmp::approx_eq!(
+            f32,
+            reading.angle(PI),
+            3f32 * PI / 2f32,
+            epsilon = 1e-5
+        ));
+    }
+
+    #[test]
+    fn biss() {
+        let position = 0x2_5A5Au64;
+        let bits = 18;
+        // no error, no warning
+        let data = (position << 2) | 0b11;
+        let frame = (data << 6) | biss_crc6(data, bits + 2) as u64;
+        let reading = biss_c(frame, bits).unwrap();
+        assert_eq!(reading.position, position as u32);
+        assert!(!reading.warning);
+
+        // corrupted bit
+        assert_eq!(biss_c(frame ^ (1 << 12), bits), Err(Error::Crc));
+
+        // warning
+        let data = (position << 2) | 0b10;
+        let frame = (data << 6) | biss_crc6(data, bits + 2) as u64;
+        assert!(biss_c(frame, bits).unwrap().warning);
+
+        // error
+        let data = (position << 2) | 0b01;
+        let frame = (data << 6) | biss_crc6(data, bits + 2) as u64;
+        assert_eq!(biss_c(frame, bits), Err(Error::EncoderError));
+    }
+
+    #[test]
+    fn spi() {
+        // 0x1234 has 5 ones, so parity bit is set
+        let reading = as504x(0x9234).unwrap();
+        assert_eq!(reading.position, 0x1234);
+        assert_eq!(reading.resolution_bits, 14);
+        assert_eq!(as504x(0x1234), Err(Error::Parity));
+        assert_eq!(as504x(0x5234), Err(Error::EncoderError));
+
+        let reading = magalpha(0xABCD, 14).unwrap();
+        assert_eq!(reading.position, 0xABCD >> 2);
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(ssi_binary(0xFFFF_FFFF, 32).unwrap().position, 0xFFFF_FFFF);
+        assert_eq!(ssi_binary(1, 0), Err(Error::InvalidBits));
+        assert_eq!(ssi_gray(1, 33), Err(Error::InvalidBits));
+        assert_eq!(magalpha(0xABCD, 16).unwrap().position, 0xABCD);
+        assert_eq!(magalpha(0xABCD, 0), Err(Error::InvalidBits));
+        assert_eq!(magalpha(0xABCD, 17), Err(Error::InvalidBits));
+        assert_eq!(biss_c(0, 62), Err(Error::InvalidBits));
+        assert_eq!(biss_crc6(0, 200), biss_crc6(0, 64));
+
+        // resolution beyond a u32 position is capped
+        let reading = Reading {
+            position: 1 << 30,
+            resolution_bits: 64,
+            warning: false,
+        };
+        assert!(float_cmp::approx_eq!(
+            f32,
+            reading.angle(0f32),
+            PI / 2f32,
+            epsilon = 1e-5
+        ));
+    }
+
+    #[test]
+    fn mechanical() {
+        let mut encoder = AbsoluteEncoder::new(PI / 2f32, 1000f32);
+
+        let state = encoder.update(&ssi_binary(1 << 10, 12).unwrap());
+        assert!(float_cmp::approx_eq!(
+            f32,
+            state.angle,
+            0f32,
+            epsilon = 1e-5
+        ));
+
+        // moving backwards over zero
+        let state = encoder.update(&ssi_binary((1 << 10) - 1, 12).unwrap());
+        let step = 2f32 * PI / 4096f32;
+        assert!(float_cmp::approx_eq!(
+            f32,
+            state.angle,
+            2f32 * PI - step,
+            epsilon = 1e-5
+        ));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            state.speed,
+            -step * 1000f32,
+            epsilon = 1e-2
+        ));
+    }
+}
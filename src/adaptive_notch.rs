@@ -0,0 +1,262 @@
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+#![allow(non_snake_case)]
+
+//! adaptive notch filter for mechanical resonances
+//!
+//! A [notch](crate::biquad::notch) keeps the speed loop from exciting a resonance, as long as you
+//! know where it is. Belts stretch, loads change, and the resonance wanders off while the notch
+//! stays put.
+//!
+//! [AdaptiveNotch] goes looking for it. It runs a second, narrow notch on the speed error
+//!
+//! H(z) = (1 + a z⁻¹ + z⁻²) / (1 + ρ a z⁻¹ + ρ² z⁻²)
+//!
+//! whose zeros sit on the unit circle at the frequency ω with a = -2 cos ω, and whose poles sit
+//! right behind them at the radius ρ. Its output is the speed error minus the resonance. A
+//! gradient descent on a minimizes what's left, which pulls the notch onto the dominant
+//! resonance. The adaptation is normalized by the signal power, so the speed of tracking doesn't
+//! depend on how strong the resonance is, and it pauses while there's hardly any signal at all.
+//!
+//! The frequency found is bounded to a configured range and retunes the actual notch in the torque
+//! reference path. Put it between the speed controller's output and the current controller's
+//! reference.
+
+use crate::biquad::{self, Biquad};
+use core::f32::consts::PI;
+#[cfg_attr(test, allow(unused_imports))]
+use num_traits::Float;
+
+/// adaptive notch errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// frequency range is empty or the initial frequency is outside of it
+    InvalidRange,
+    /// pole radius is not between 0 and 1, or adaptation gain is not positive
+    InvalidAdaptation,
+    /// notch design failed
+    Design(biquad::Error),
+}
+
+/// configuration struct for adaptive notch construction
+#[derive(PartialEq, Debug)]
+pub struct AdaptiveNotchConfig {
+    /// lowest center frequency in Hz
+    pub f_min: f32,
+    /// highest center frequency in Hz
+    pub f_max: f32,
+    /// center frequency in Hz to start from
+    pub f_initial: f32,
+    /// quality factor of the notch in the torque reference path
+    pub Q: f32,
+    /// pole radius of the detecting notch, below but close to 1. The closer, the narrower.
+    pub pole_radius: f32,
+    /// normalized adaptation gain. Larger values track faster, but noisier.
+    pub adaptation_gain: f32,
+    /// signal power of the speed error below which the adaptation pauses
+    pub power_min: f32,
+}
+
+/// adaptive notch filter state
+#[derive(PartialEq, Debug)]
+pub struct AdaptiveNotch {
+    /// detector state s[k-1]
+    s1: f32,
+    /// detector state s[k-2]
+    s2: f32,
+    /// detector coefficient a = -2 cos ω
+    a: f32,
+    /// lower bound of a
+    a_min: f32,
+    /// upper bound of a
+    a_max: f32,
+    /// signal power estimate
+    power: f32,
+    /// notch in the torque reference path
+    notch: Biquad,
+    /// sampling frequency
+    f_sample: f32,
+    /// configuration
+    config: AdaptiveNotchConfig,
+}
+
+impl AdaptiveNotch {
+    /// create new adaptive notch
+    pub fn new(cfg: AdaptiveNotchConfig, f_sample_Hz: f32) -> Result<AdaptiveNotch, Error> {
+        if !(cfg.f_min < cfg.f_max && cfg.f_min <= cfg.f_initial && cfg.f_initial <= cfg.f_max) {
+            return Err(Error::InvalidRange);
+        }
+        if !(cfg.pole_radius > 0f32
+            && cfg.pole_radius < 1f32
+            && cfg.adaptation_gain.is_finite()
+            && cfg.adaptation_gain > 0f32)
+        {
+            return Err(Error::InvalidAdaptation);
+        }
+        let notch = biquad::notch(cfg.f_initial, cfg.Q, f_sample_Hz).map_err(Error::Design)?;
+        // also make sure the whole range can be designed
+        biquad::notch(cfg.f_min, cfg.Q, f_sample_Hz).map_err(Error::Design)?;
+        biquad::notch(cfg.f_max, cfg.Q, f_sample_Hz).map_err(Error::Design)?;
+
+        let to_a = |f: f32| -2f32 * (2f32 * PI * f / f_sample_Hz).cos();
+        Ok(AdaptiveNotch {
+            s1: 0f32,
+            s2: 0f32,
+            a: to_a(cfg.f_initial),
+            a_min: to_a(cfg.f_min),
+            a_max: to_a(cfg.f_max),
+            power: 0f32,
+            notch: Biquad::new(notch),
+            f_sample: f_sample_Hz,
+            config: cfg,
+        })
+    }
+
+    /// run this method on every sample with the speed error to track the resonance in and the
+    /// torque reference to filter. Returns the filtered torque reference.
+    pub fn update(&mut self, speed_error: f32, torque_reference: f32) -> f32 {
+        let rho = self.config.pole_radius;
+
+        // detecting notch
+        let s = speed_error - rho * self.a * self.s1 - rho * rho * self.s2;
+        let residual = s + self.a * self.s1 + self.s2;
+
+        // normalized gradient descent on the residual
+        self.power = 0.99f32 * self.power + 0.01f32 * self.s1 * self.s1;
+        if self.power > self.config.power_min {
+            let a = self.a - self.config.adaptation_gain * residual * self.s1 / self.power;
+            let a = a.clamp(self.a_min, self.a_max);
+            if a != self.a {
+                self.a = a;
+                // straight from a = -2 cos ω, range and Q were checked on construction
+                let cos_w = -a / 2f32;
+                let sin_w = (1f32 - cos_w * cos_w).max(0f32).sqrt();
+                self.notch.set_coefficients(biquad::notch_prewarped(
+                    cos_w,
+                    sin_w / (2f32 * self.config.Q),
+                ));
+            }
+        }
+        self.s2 = self.s1;
+        self.s1 = s;
+
+        self.notch.update(torque_reference)
+    }
+
+    /// center frequency in Hz the notch currently sits on
+    pub fn frequency(&self) -> f32 {
+        (-self.a / 2f32).acos() * self.f_sample / (2f32 * PI)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const F_SAMPLE: f32 = 10000f32;
+
+    fn config() -> AdaptiveNotchConfig {
+        AdaptiveNotchConfig {
+            f_min: 100f32,
+            f_max: 800f32,
+            f_initial: 500f32,
+            Q: 2f32,
+            pole_radius: 0.95f32,
+            adaptation_gain: 0.02f32,
+            power_min: 1e-6f32,
+        }
+    }
+
+    /// feed a resonance at a frequency into the speed error for a number of samples
+    fn resonate(notch: &mut AdaptiveNotch, f_Hz: f32, samples: usize) {
+        for n in 0..samples {
+            let resonance = 0.1f32 * (2f32 * PI * f_Hz * n as f32 / F_SAMPLE).sin();
+            // some slow speed error besides the resonance
+            let speed_error = resonance + 0.05f32 * (2f32 * PI * 5f32 * n as f32 / F_SAMPLE).sin();
+            notch.update(speed_error, 0f32);
+        }
+    }
+
+    #[test]
+    fn tracking() {
+        let mut notch = AdaptiveNotch::new(config(), F_SAMPLE).unwrap();
+        resonate(&mut notch, 300f32, 10000);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            notch.frequency(),
+            300f32,
+            epsilon = 5f32
+        ));
+
+        // torque reference at the resonance is blocked
+        let mut amplitude = 0f32;
+        for n in 0..1000 {
+            let torque = (2f32 * PI * 300f32 * n as f32 / F_SAMPLE).sin();
+            let filtered = notch.update(0f32, torque);
+            if n > 500 {
+                amplitude = amplitude.max(filtered.abs());
+            }
+        }
+        assert!(amplitude < 0.1f32);
+
+        // resonance moves with the load
+        resonate(&mut notch, 450f32, 10000);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            notch.frequency(),
+            450f32,
+            epsilon = 5f32
+        ));
+    }
+
+    #[test]
+    fn bounds() {
+        let mut notch = AdaptiveNotch::new(config(), F_SAMPLE).unwrap();
+        resonate(&mut notch, 50f32, 20000);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            notch.frequency(),
+            100f32,
+            epsilon = 0.5f32
+        ));
+
+        let cfg = AdaptiveNotchConfig {
+            f_initial: 900f32,
+            ..config()
+        };
+        assert_eq!(AdaptiveNotch::new(cfg, F_SAMPLE), Err(Error::InvalidRange));
+    }
+
+    #[test]
+    fn invalid_adaptation() {
+        for (pole_radius, adaptation_gain) in [(1f32, 0.02f32), (0f32, 0.02f32), (0.95f32, 0f32)] {
+            let cfg = AdaptiveNotchConfig {
+                pole_radius,
+                adaptation_gain,
+                ..config()
+            };
+            assert_eq!(
+                AdaptiveNotch::new(cfg, F_SAMPLE),
+                Err(Error::InvalidAdaptation)
+            );
+        }
+    }
+
+    #[test]
+    fn retuned_coefficients() {
+        // notch in the torque path matches a fresh design at the frequency found
+        let mut notch = AdaptiveNotch::new(config(), F_SAMPLE).unwrap();
+        resonate(&mut notch, 300f32, 10000);
+        let designed = biquad::notch(notch.frequency(), 2f32, F_SAMPLE).unwrap();
+        let retuned = notch.notch.coefficients();
+        for (x, y) in [
+            (retuned.b0, designed.b0),
+            (retuned.b1, designed.b1),
+            (retuned.b2, designed.b2),
+            (retuned.a1, designed.a1),
+            (retuned.a2, designed.a2),
+        ] {
+            assert!(float_cmp::approx_eq!(f32, x, y, epsilon = 1e-5));
+        }
+    }
+}
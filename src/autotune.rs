@@ -0,0 +1,370 @@
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+#![allow(non_snake_case)]
+
+//! relay feedback auto tuning
+//!
+//! Åström and Hägglund found a neat trick to find out how a plant likes to be controlled: replace
+//! the controller by a relay. Whenever the error is positive, push with +d, whenever it's negative,
+//! pull with -d. Most plants start to oscillate around the setpoint, and they do it right at their
+//! ultimate period T_u, the period a P controller at the stability limit would produce.
+//!
+//! From the oscillation amplitude a, the ultimate gain follows as
+//!
+//! K_u = 4 d / (π √(a² - ε²))
+//!
+//! with ε being the hysteresis of the relay, which keeps noise from switching it back and forth.
+//! Feed K_u and T_u into one of the classic rules and out come the gains.
+//!
+//! [Autotune] runs this as a state machine. Call it from your control interrupt with the control
+//! error, where you'd call [crate::pid::PID::update] otherwise, and apply its output until it's
+//! done. If the error leaves the allowed range or the oscillation takes too long, it aborts with an
+//! error. Apply a safe output then, and don't use anything it measured.
+
+use crate::pid::{self, PIDConfig};
+use core::f32::consts::PI;
+#[cfg_attr(test, allow(unused_imports))]
+use num_traits::Float;
+
+/// auto tuning errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// control error exceeded the allowed range
+    LimitExceeded,
+    /// no oscillation was measured within the timeout
+    Timeout,
+    /// oscillation amplitude is within the relay hysteresis
+    NoOscillation,
+    /// resulting gains or limits are invalid
+    InvalidConfig(pid::Error),
+    /// auto tuning was aborted by an earlier error
+    Aborted,
+}
+
+/// tuning rule
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Rule {
+    /// Ziegler-Nichols P controller
+    ZieglerNicholsP,
+    /// Ziegler-Nichols PI controller
+    ZieglerNicholsPI,
+    /// Ziegler-Nichols PID controller, fast but with plenty of overshoot
+    ZieglerNicholsPID,
+    /// Tyreus-Luyben PI controller, calmer than Ziegler-Nichols
+    TyreusLuybenPI,
+    /// Tyreus-Luyben PID controller, calmer than Ziegler-Nichols
+    TyreusLuybenPID,
+}
+
+impl Rule {
+    /// gains K_p, K_i and K_d for [crate::pid::PID] from ultimate gain and period
+    pub fn gains(&self, K_u: f32, T_u: f32) -> (f32, f32, f32) {
+        // K_p, T_i and T_d of the standard form
+        let (K_p, T_i, T_d) = match self {
+            Rule::ZieglerNicholsP => (0.5f32 * K_u, f32::INFINITY, 0f32),
+            Rule::ZieglerNicholsPI => (0.45f32 * K_u, T_u / 1.2f32, 0f32),
+            Rule::ZieglerNicholsPID => (0.6f32 * K_u, T_u / 2f32, T_u / 8f32),
+            Rule::TyreusLuybenPI => (K_u / 3.2f32, 2.2f32 * T_u, 0f32),
+            Rule::TyreusLuybenPID => (K_u / 2.2f32, 2.2f32 * T_u, T_u / 6.3f32),
+        };
+        (K_p, 1f32 / T_i, T_d)
+    }
+}
+
+/// configuration struct for auto tuning construction
+#[derive(PartialEq, Debug)]
+pub struct AutotuneConfig {
+    /// relay output amplitude d
+    pub amplitude: f32,
+    /// output the relay switches around, e.g. to hold a load
+    pub bias: f32,
+    /// relay hysteresis ε on the control error
+    pub hysteresis: f32,
+    /// highest control error magnitude before aborting
+    pub error_max: f32,
+    /// number of oscillation periods to average over
+    pub periods: u8,
+    /// time in seconds to wait for all periods before aborting
+    pub timeout: f32,
+    /// tuning rule
+    pub rule: Rule,
+    /// low output limit of the proposed controller
+    pub limit_low: f32,
+    /// high output limit of the proposed controller
+    pub limit_high: f32,
+}
+
+/// output of an auto tuning step
+#[derive(PartialEq, Debug)]
+pub enum Output {
+    /// apply this output to the plant
+    Drive(f32),
+    /// auto tuning finished, switch to the controller
+    Done(AutotuneResult),
+}
+
+/// result of an auto tuning
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct AutotuneResult {
+    /// ultimate gain K_u
+    pub ultimate_gain: f32,
+    /// ultimate period T_u in seconds
+    pub ultimate_period: f32,
+    /// proposed controller configuration
+    pub config: PIDConfig,
+}
+
+/// relay auto tuning state machine
+#[derive(PartialEq, Debug)]
+pub struct Autotune {
+    /// relay is pushing
+    relay_high: bool,
+    /// time since start
+    t: f32,
+    /// time of the most recent switch to pushing
+    t_switch: Option<f32>,
+    /// highest error of the running period
+    peak_high: f32,
+    /// lowest error of the running period
+    peak_low: f32,
+    /// measured full periods, the first one is skipped as transient
+    periods: u8,
+    /// sum of period durations
+    period_sum: f32,
+    /// sum of peak to peak amplitudes
+    amplitude_sum: f32,
+    /// auto tuning stopped for good
+    aborted: bool,
+    /// sample time
+    t_sample: f32,
+    /// configuration
+    config: AutotuneConfig,
+}
+
+impl Autotune {
+    /// create new auto tuning
+    pub fn new(cfg: AutotuneConfig, f_sample_Hz: f32) -> Autotune {
+        Autotune {
+            relay_high: true,
+            t: 0f32,
+            t_switch: None,
+            peak_high: f32::MIN,
+            peak_low: f32::MAX,
+            periods: 0,
+            period_sum: 0f32,
+            amplitude_sum: 0f32,
+            aborted: false,
+            t_sample: 1f32 / f_sample_Hz,
+            config: cfg,
+        }
+    }
+
+    /// run this method on every sample with the control error. Apply the returned output until
+    /// auto tuning is done. After an error, it stays aborted and returns [Error::Aborted].
+    pub fn update(&mut self, error: f32) -> Result<Output, Error> {
+        if self.aborted {
+            return Err(Error::Aborted);
+        }
+
+        self.t += self.t_sample;
+        if error.abs() > self.config.error_max {
+            return self.abort(Error::LimitExceeded);
+        }
+        if self.t > self.config.timeout {
+            return self.abort(Error::Timeout);
+        }
+
+        self.peak_high = self.peak_high.max(error);
+        self.peak_low = self.peak_low.min(error);
+
+        // relay with hysteresis
+        if self.relay_high && error < -self.config.hysteresis {
+            self.relay_high = false;
+        } else if !self.relay_high && error > self.config.hysteresis {
+            self.relay_high = true;
+            if let Some(result) = self.period_done() {
+                return result.map(Output::Done);
+            }
+        }
+
+        let direction = if self.relay_high { 1f32 } else { -1f32 };
+        Ok(Output::Drive(
+            self.config.bias + direction * self.config.amplitude,
+        ))
+    }
+
+    /// count a full period on every switch to pushing. Returns the result once enough periods
+    /// were measured.
+    fn period_done(&mut self) -> Option<Result<AutotuneResult, Error>> {
+        if let Some(t_switch) = self.t_switch {
+            // first period is still settling
+            if self.periods > 0 {
+                self.period_sum += self.t - t_switch;
+                self.amplitude_sum += (self.peak_high - self.peak_low) / 2f32;
+            }
+            self.periods += 1;
+        }
+        self.t_switch = Some(self.t);
+        self.peak_high = f32::MIN;
+        self.peak_low = f32::MAX;
+
+        if self.periods <= self.config.periods {
+            return None;
+        }
+        let result = self.result();
+        self.aborted = result.is_err();
+        Some(result)
+    }
+
+    /// calculate gains from measured oscillation
+    fn result(&self) -> Result<AutotuneResult, Error> {
+        let periods = self.config.periods as f32;
+        let T_u = self.period_sum / periods;
+        let a = self.amplitude_sum / periods;
+        let epsilon = self.config.hysteresis;
+        if a <= epsilon {
+            return Err(Error::NoOscillation);
+        }
+        let K_u = 4f32 * self.config.amplitude / (PI * (a * a - epsilon * epsilon).sqrt());
+
+        let (K_p, K_i, K_d) = self.config.rule.gains(K_u, T_u);
+        let config = PIDConfig::new(
+            K_p,
+            K_i,
+            K_d,
+            (self.config.limit_low, self.config.limit_high),
+        )
+        .map_err(Error::InvalidConfig)?;
+
+        Ok(AutotuneResult {
+            ultimate_gain: K_u,
+            ultimate_period: T_u,
+            config,
+        })
+    }
+
+    /// stop auto tuning for good
+    fn abort(&mut self, error: Error) -> Result<Output, Error> {
+        self.aborted = true;
+        Err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const F_SAMPLE: f32 = 1000f32;
+
+    fn config(rule: Rule) -> AutotuneConfig {
+        AutotuneConfig {
+            amplitude: 1f32,
+            bias: 0f32,
+            hysteresis: 0.01f32,
+            error_max: 1f32,
+            periods: 3,
+            timeout: 10f32,
+            rule,
+            limit_low: -10f32,
+            limit_high: 10f32,
+        }
+    }
+
+    /// three first order lags of 0.1 s in a row: ultimate gain 8 at ω = √3 / 0.1 s
+    struct Plant {
+        states: [f32; 3],
+    }
+
+    impl Plant {
+        fn update(&mut self, input: f32) -> f32 {
+            let mut input = input;
+            for state in self.states.iter_mut() {
+                *state += (input - *state) / (0.1f32 * F_SAMPLE);
+                input = *state;
+            }
+            input
+        }
+    }
+
+    fn run(autotune: &mut Autotune, setpoint: f32) -> Result<AutotuneResult, Error> {
+        let mut plant = Plant { states: [0f32; 3] };
+        let mut output = 0f32;
+        loop {
+            let measurement = plant.update(output);
+            match autotune.update(setpoint - measurement)? {
+                Output::Drive(drive) => output = drive,
+                Output::Done(result) => return Ok(result),
+            }
+        }
+    }
+
+    #[test]
+    fn relay() {
+        let mut autotune = Autotune::new(config(Rule::ZieglerNicholsPI), F_SAMPLE);
+        let result = run(&mut autotune, 0f32).unwrap();
+
+        // describing function is an approximation, close enough for tuning
+        assert!(float_cmp::approx_eq!(
+            f32,
+            result.ultimate_gain,
+            8f32,
+            epsilon = 0.8
+        ));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            result.ultimate_period,
+            2f32 * PI * 0.1f32 / 3f32.sqrt(),
+            epsilon = 0.02
+        ));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            result.config.K_p(),
+            0.45f32 * result.ultimate_gain,
+            epsilon = 1e-5
+        ));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            result.config.K_i(),
+            1.2f32 / result.ultimate_period,
+            epsilon = 1e-3
+        ));
+    }
+
+    #[test]
+    fn rules() {
+        let (K_p, K_i, K_d) = Rule::TyreusLuybenPID.gains(2.2f32, 1f32);
+        assert_eq!(K_p, 1f32);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            K_i,
+            1f32 / 2.2f32,
+            epsilon = 1e-6
+        ));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            K_d,
+            1f32 / 6.3f32,
+            epsilon = 1e-6
+        ));
+
+        let (_, K_i, K_d) = Rule::ZieglerNicholsP.gains(1f32, 1f32);
+        assert_eq!((K_i, K_d), (0f32, 0f32));
+    }
+
+    #[test]
+    fn abort() {
+        // setpoint far away, the plant can't follow within the allowed error
+        let mut autotune = Autotune::new(config(Rule::ZieglerNicholsPID), F_SAMPLE);
+        assert_eq!(run(&mut autotune, 5f32), Err(Error::LimitExceeded));
+        assert_eq!(autotune.update(0f32), Err(Error::Aborted));
+
+        let mut autotune = Autotune::new(
+            AutotuneConfig {
+                timeout: 0.5f32,
+                ..config(Rule::ZieglerNicholsPID)
+            },
+            F_SAMPLE,
+        );
+        assert_eq!(run(&mut autotune, 0f32), Err(Error::Timeout));
+    }
+}
@@ -0,0 +1,454 @@
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+#![allow(non_snake_case)]
+
+//! sensorless six-step commutation by back-EMF zero crossing detection
+//!
+//! In six-step operation one phase is always floating. Its terminal voltage against the motor's
+//! (virtual) neutral point is the induced voltage of that phase, which crosses zero right in the
+//! middle of every commutation step. Measure the time between two zero crossings, wait half of it
+//! (30° electrical) after a crossing, and you know when to commutate next. No hall sensors needed.
+//!
+//! Right after a commutation the floating phase is still busy freewheeling the current of its
+//! previous life, so its voltage is garbage for a while. That's what the blanking time is for.
+//!
+//! At standstill there is no induced voltage at all, so the motor is started in open loop: the
+//! sectors are stepped with a decreasing commutation period until enough zero crossings have been
+//! seen in a row. From there on the zero crossings take over. If they ever go missing, the motor
+//! lost sync and needs to be started again.
+//!
+//! Sector numbers and the [Mechanical] state emitted on every commutation are the same as the ones
+//! of [crate::hall::Hall], so [crate::six_step::SixStep] can be fed from here directly.
+
+use crate::motor::Mechanical;
+use crate::six_step::Direction;
+use core::f32::consts::PI;
+
+/// floating phase in every sector, independent of direction
+const SECTOR_TO_FLOATING_PHASE: [usize; 6] = [0, 2, 1, 0, 2, 1];
+
+/// back-EMF commutation errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// no zero crossing was detected within two commutation periods
+    LossOfSync,
+    /// open loop ramp finished without locking onto the zero crossings
+    StartupFailed,
+    /// blanking not within 0 to 0.5, ramp periods, ramp time or sampling frequency not positive
+    /// and finite, or no sync crossings
+    InvalidConfig,
+}
+
+/// operating mode of back-EMF commutation
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Mode {
+    /// not commutating. Call [Bemf::start] to run the open loop ramp
+    Stopped,
+    /// open loop start up with forced commutation
+    Ramp,
+    /// commutation by zero crossing detection
+    ClosedLoop,
+}
+
+/// configuration struct for back-EMF commutation
+#[derive(PartialEq, Debug)]
+pub struct BemfConfig {
+    /// rotational direction
+    pub direction: Direction,
+    /// part of the commutation period in which the floating phase is ignored after commutation,
+    /// from 0 to 0.5
+    pub blanking: f32,
+    /// commutation period at the start of the open loop ramp in seconds
+    pub ramp_period_start: f32,
+    /// commutation period at the end of the open loop ramp in seconds
+    pub ramp_period_end: f32,
+    /// duration of the open loop ramp in seconds
+    pub ramp_time: f32,
+    /// number of consecutive zero crossings during ramp to switch to closed loop
+    pub sync_crossings: u8,
+}
+
+/// back-EMF zero crossing commutation state
+#[derive(PartialEq, Debug)]
+pub struct Bemf {
+    /// current sector from 0 to 5
+    sector: i8,
+    /// operating mode
+    mode: Mode,
+    /// time since last commutation
+    t_commutation: f32,
+    /// time since last zero crossing
+    t_zero_crossing: f32,
+    /// time since start of ramp
+    t_ramp: f32,
+    /// duration of one commutation step (60° electrical)
+    period: f32,
+    /// zero crossing was detected in current commutation step
+    zero_crossing_found: bool,
+    /// consecutive zero crossings seen during ramp
+    crossings: u8,
+    /// most recent speed for acceleration calculation, none before the first commutation
+    speed_recent: Option<f32>,
+    /// sample time
+    t_sample: f32,
+    /// configuration
+    config: BemfConfig,
+}
+
+impl Bemf {
+    /// create new back-EMF commutation. Sector is the one the rotor was aligned to before start.
+    pub fn new(cfg: BemfConfig, sector: i8, f_sample_Hz: f32) -> Result<Bemf, Error> {
+        let positive = |value: f32| value.is_finite() && value > 0f32;
+        if !((0f32..=0.5f32).contains(&cfg.blanking)
+            && positive(cfg.ramp_period_start)
+            && positive(cfg.ramp_period_end)
+            && positive(cfg.ramp_time)
+            && cfg.sync_crossings > 0
+            && positive(f_sample_Hz))
+        {
+            return Err(Error::InvalidConfig);
+        }
+        Ok(Bemf {
+            sector: sector.rem_euclid(6),
+            mode: Mode::Stopped,
+            t_commutation: 0f32,
+            t_zero_crossing: 0f32,
+            t_ramp: 0f32,
+            period: cfg.ramp_period_start,
+            zero_crossing_found: false,
+            crossings: 0,
+            speed_recent: None,
+            t_sample: 1f32 / f_sample_Hz,
+            config: cfg,
+        })
+    }
+
+    /// start open loop ramp from the current sector
+    pub fn start(&mut self) {
+        self.mode = Mode::Ramp;
+        self.t_commutation = 0f32;
+        self.t_zero_crossing = 0f32;
+        self.t_ramp = 0f32;
+        self.period = self.config.ramp_period_start;
+        self.zero_crossing_found = false;
+        self.crossings = 0;
+        self.speed_recent = None;
+    }
+
+    /// stop commutation
+    pub fn stop(&mut self) {
+        self.mode = Mode::Stopped;
+    }
+
+    /// current sector from 0 to 5, numbered like [crate::hall::Hall::sector]
+    pub fn sector(&self) -> i8 {
+        self.sector
+    }
+
+    /// current operating mode
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// index of the floating phase whose voltage has to be sampled in the current sector
+    pub fn floating_phase(&self) -> usize {
+        SECTOR_TO_FLOATING_PHASE[self.sector as usize]
+    }
+
+    /// run this method on every sample with the voltage of the floating phase and the virtual
+    /// neutral point. Returns the new rotor state whenever the sector changed.
+    pub fn update(&mut self, v_floating: f32, v_neutral: f32) -> Result<Option<Mechanical>, Error> {
+        if self.mode == Mode::Stopped {
+            return Ok(None);
+        }
+
+        self.t_commutation += self.t_sample;
+        self.t_zero_crossing += self.t_sample;
+
+        if self.mode == Mode::Ramp {
+            self.t_ramp += self.t_sample;
+            let progress = (self.t_ramp / self.config.ramp_time).min(1f32);
+            self.period = self.config.ramp_period_start
+                + (self.config.ramp_period_end - self.config.ramp_period_start) * progress;
+        }
+
+        // zero crossing detection after blanking
+        if !self.zero_crossing_found && self.t_commutation >= self.config.blanking * self.period {
+            let v_bemf = v_floating - v_neutral;
+            let crossed = if self.bemf_rising() {
+                v_bemf > 0f32
+            } else {
+                v_bemf < 0f32
+            };
+            if crossed {
+                self.zero_crossing_found = true;
+                match self.mode {
+                    Mode::ClosedLoop => self.period = self.t_zero_crossing,
+                    _ => {
+                        self.crossings = self.crossings.saturating_add(1);
+                        if self.crossings >= self.config.sync_crossings {
+                            self.mode = Mode::ClosedLoop;
+                        }
+                    }
+                }
+                self.t_zero_crossing = 0f32;
+            }
+        }
+
+        match self.mode {
+            Mode::Ramp => {
+                if self.t_commutation >= self.period {
+                    if !self.zero_crossing_found {
+                        self.crossings = 0;
+                    }
+                    if self.t_ramp >= self.config.ramp_time {
+                        self.mode = Mode::Stopped;
+                        return Err(Error::StartupFailed);
+                    }
+                    return Ok(Some(self.commutate()));
+                }
+            }
+            Mode::ClosedLoop => {
+                // commutate 30° after zero crossing
+                if self.zero_crossing_found && self.t_zero_crossing >= self.period / 2f32 {
+                    return Ok(Some(self.commutate()));
+                }
+                if self.t_commutation > 2f32 * self.period {
+                    self.mode = Mode::Stopped;
+                    return Err(Error::LossOfSync);
+                }
+            }
+            Mode::Stopped => (),
+        }
+
+        Ok(None)
+    }
+
+    /// induced voltage of the floating phase rises in the current sector. The sign of the speed
+    /// cancels against the direction of travel, so it's the same in both directions.
+    fn bemf_rising(&self) -> bool {
+        self.sector % 2 == 1
+    }
+
+    /// step to next sector and calculate the rotor state at the sector border
+    fn commutate(&mut self) -> Mechanical {
+        let (step, angle_offset, speed) = match self.config.direction {
+            Direction::Clockwise => (1, -PI / 6f32, PI / 3f32 / self.period),
+            Direction::CounterClockwise => (-1, PI / 6f32, -PI / 3f32 / self.period),
+        };
+        self.sector = (self.sector + step).rem_euclid(6);
+        self.t_commutation = 0f32;
+        self.zero_crossing_found = false;
+
+        // acceleration needs two known commutation periods, a start from 0 isn't one
+        let acceleration = self
+            .speed_recent
+            .map_or(0f32, |speed_recent| (speed - speed_recent) / self.period);
+        self.speed_recent = Some(speed);
+
+        let mut angle = self.sector as f32 * PI / 3f32 + angle_offset;
+        if angle < 0f32 {
+            angle += 2f32 * PI;
+        }
+
+        Mechanical {
+            angle,
+            speed,
+            acceleration,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::motor::wrap_angle_diff;
+
+    const F_SAMPLE: f32 = 20000f32;
+    /// electrical rotor speed of 50 Hz
+    const SPEED: f32 = 2f32 * PI * 50f32;
+
+    fn config() -> BemfConfig {
+        BemfConfig {
+            direction: Direction::Clockwise,
+            blanking: 0.2f32,
+            ramp_period_start: PI / 3f32 / SPEED,
+            ramp_period_end: PI / 3f32 / SPEED,
+            ramp_time: 0.1f32,
+            sync_crossings: 3,
+        }
+    }
+
+    /// induced voltage of phase at rotor angle and speed, normalized to SPEED
+    fn bemf(angle: f32, speed: f32, phase: usize) -> f32 {
+        -speed / SPEED * (angle - phase as f32 * 2f32 * PI / 3f32).sin()
+    }
+
+    #[test]
+    fn zero_crossing_commutation() {
+        let mut bemf_cm = Bemf::new(config(), 0, F_SAMPLE).unwrap();
+        assert_eq!(bemf_cm.update(0f32, 0f32), Ok(None));
+        bemf_cm.start();
+        assert_eq!(bemf_cm.mode(), Mode::Ramp);
+
+        // rotor enters sector 0 at start
+        let mut angle = -PI / 6f32;
+        let mut commutations = 0;
+        for _ in 0..(F_SAMPLE as usize / 10) {
+            angle += SPEED / F_SAMPLE;
+            let v = bemf(angle, SPEED, bemf_cm.floating_phase());
+            if let Some(state) = bemf_cm.update(v, 0f32).unwrap() {
+                commutations += 1;
+                if bemf_cm.mode() == Mode::ClosedLoop {
+                    let mut expected = angle % (2f32 * PI);
+                    if expected < 0f32 {
+                        expected += 2f32 * PI;
+                    }
+                    assert!(float_cmp::approx_eq!(
+                        f32,
+                        state.angle,
+                        expected,
+                        epsilon = 0.05
+                    ));
+                    assert!(float_cmp::approx_eq!(
+                        f32,
+                        state.speed,
+                        SPEED,
+                        epsilon = 5f32
+                    ));
+                }
+            }
+        }
+        assert_eq!(bemf_cm.mode(), Mode::ClosedLoop);
+        // 0.1 s at 50 Hz are 5 electrical revolutions
+        assert!((29..=31).contains(&commutations));
+    }
+
+    #[test]
+    fn counter_clockwise() {
+        let cfg = BemfConfig {
+            direction: Direction::CounterClockwise,
+            ..config()
+        };
+        let mut bemf_cm = Bemf::new(cfg, 0, F_SAMPLE).unwrap();
+        bemf_cm.start();
+
+        // rotor enters sector 0 from above at start
+        let mut angle = PI / 6f32;
+        let mut closed_loop_commutations = 0;
+        for _ in 0..(F_SAMPLE as usize / 10) {
+            angle -= SPEED / F_SAMPLE;
+            let v = bemf(angle, -SPEED, bemf_cm.floating_phase());
+            if let Some(state) = bemf_cm.update(v, 0f32).unwrap() {
+                if bemf_cm.mode() == Mode::ClosedLoop {
+                    closed_loop_commutations += 1;
+                    let angle_diff = wrap_angle_diff(state.angle - angle);
+                    assert!(float_cmp::approx_eq!(f32, angle_diff, 0f32, epsilon = 0.05));
+                    assert!(float_cmp::approx_eq!(
+                        f32,
+                        state.speed,
+                        -SPEED,
+                        epsilon = 5f32
+                    ));
+                }
+            }
+        }
+        assert_eq!(bemf_cm.mode(), Mode::ClosedLoop);
+        assert!(closed_loop_commutations > 20);
+    }
+
+    #[test]
+    fn first_commutation() {
+        let mut bemf_cm = Bemf::new(config(), 0, F_SAMPLE).unwrap();
+
+        // no acceleration spike from standstill, neither on start nor on restart
+        for _ in 0..2 {
+            bemf_cm.start();
+            let mut states = [0f32; 2];
+            let mut commutations = 0;
+            while commutations < 2 {
+                if let Some(state) = bemf_cm.update(0f32, 0f32).unwrap() {
+                    states[commutations] = state.acceleration;
+                    commutations += 1;
+                }
+            }
+            assert_eq!(states, [0f32, 0f32]);
+            bemf_cm.stop();
+        }
+    }
+
+    #[test]
+    fn loss_of_sync() {
+        let mut bemf_cm = Bemf::new(config(), 0, F_SAMPLE).unwrap();
+        bemf_cm.start();
+
+        let mut angle = -PI / 6f32;
+        for _ in 0..(F_SAMPLE as usize / 50) {
+            angle += SPEED / F_SAMPLE;
+            let v = bemf(angle, SPEED, bemf_cm.floating_phase());
+            bemf_cm.update(v, 0f32).unwrap();
+        }
+        assert_eq!(bemf_cm.mode(), Mode::ClosedLoop);
+
+        // rotor got stuck, no more induced voltage
+        let mut result = Ok(None);
+        for _ in 0..(F_SAMPLE as usize / 50) {
+            result = bemf_cm.update(0f32, 0f32);
+            if result.is_err() {
+                break;
+            }
+        }
+        assert_eq!(result, Err(Error::LossOfSync));
+        assert_eq!(bemf_cm.mode(), Mode::Stopped);
+    }
+
+    #[test]
+    fn invalid_config() {
+        let invalid = [
+            BemfConfig {
+                blanking: 0.6f32,
+                ..config()
+            },
+            BemfConfig {
+                blanking: -0.1f32,
+                ..config()
+            },
+            BemfConfig {
+                ramp_period_start: 0f32,
+                ..config()
+            },
+            BemfConfig {
+                ramp_period_end: f32::INFINITY,
+                ..config()
+            },
+            BemfConfig {
+                ramp_time: -1f32,
+                ..config()
+            },
+            BemfConfig {
+                sync_crossings: 0,
+                ..config()
+            },
+        ];
+        for cfg in invalid {
+            assert_eq!(Bemf::new(cfg, 0, F_SAMPLE), Err(Error::InvalidConfig));
+        }
+        assert_eq!(Bemf::new(config(), 0, 0f32), Err(Error::InvalidConfig));
+    }
+
+    #[test]
+    fn startup_failed() {
+        let mut bemf_cm = Bemf::new(config(), 0, F_SAMPLE).unwrap();
+        bemf_cm.start();
+
+        // rotor does not move at all
+        let mut result = Ok(None);
+        for _ in 0..(F_SAMPLE as usize / 5) {
+            result = bemf_cm.update(0f32, 0f32);
+            if result.is_err() {
+                break;
+            }
+        }
+        assert_eq!(result, Err(Error::StartupFailed));
+    }
+}
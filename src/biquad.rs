@@ -0,0 +1,314 @@
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+#![allow(non_snake_case)]
+
+//! biquad filters
+//!
+//! A [PT1](crate::pt1::PT1) is fine to take the edge off a noisy signal, but it's only first order.
+//! Mechanics love to resonate, and to keep a speed loop from exciting a resonance you need
+//! something sharper: second order low and high passes, band passes and notches. All of these are
+//! a biquad, a filter with two poles and two zeros
+//!
+//! H(z) = (b0 + b1 z⁻¹ + b2 z⁻²) / (1 + a1 z⁻¹ + a2 z⁻²)
+//!
+//! that only differ in their coefficients. The design functions calculate them from cutoff or
+//! center frequency and quality factor Q, following the well known [audio EQ
+//! cookbook](https://www.w3.org/TR/audio-eq-cookbook/). Q of 1/√2 gives a low or high pass without
+//! peaking, higher Q makes band passes and notches narrower.
+//!
+//! [Biquad] runs them in transposed direct form II, which needs only two states and behaves well
+//! with floats. Need steeper slopes? Put several sections in a row with [Cascade].
+
+use core::f32::consts::PI;
+#[cfg_attr(test, allow(unused_imports))]
+use num_traits::Float;
+
+/// biquad design errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// frequency is not between 0 and half the sample rate
+    InvalidFrequency,
+    /// quality factor is not a positive finite number
+    InvalidQ,
+}
+
+/// biquad coefficients, normalized to a0 = 1
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Coefficients {
+    /// input coefficient
+    pub b0: f32,
+    /// input coefficient delayed by one sample
+    pub b1: f32,
+    /// input coefficient delayed by two samples
+    pub b2: f32,
+    /// output coefficient delayed by one sample
+    pub a1: f32,
+    /// output coefficient delayed by two samples
+    pub a2: f32,
+}
+
+/// second order low pass
+pub fn lowpass(f_cutoff_Hz: f32, Q: f32, f_sample_Hz: f32) -> Result<Coefficients, Error> {
+    let (cos_w, alpha) = prewarp(f_cutoff_Hz, Q, f_sample_Hz)?;
+    let b1 = 1f32 - cos_w;
+    Ok(normalize(
+        b1 / 2f32,
+        b1,
+        b1 / 2f32,
+        1f32 + alpha,
+        -2f32 * cos_w,
+        1f32 - alpha,
+    ))
+}
+
+/// second order high pass
+pub fn highpass(f_cutoff_Hz: f32, Q: f32, f_sample_Hz: f32) -> Result<Coefficients, Error> {
+    let (cos_w, alpha) = prewarp(f_cutoff_Hz, Q, f_sample_Hz)?;
+    let b1 = -(1f32 + cos_w);
+    Ok(normalize(
+        -b1 / 2f32,
+        b1,
+        -b1 / 2f32,
+        1f32 + alpha,
+        -2f32 * cos_w,
+        1f32 - alpha,
+    ))
+}
+
+/// second order band pass with a gain of 1 at the center frequency
+pub fn bandpass(f_center_Hz: f32, Q: f32, f_sample_Hz: f32) -> Result<Coefficients, Error> {
+    let (cos_w, alpha) = prewarp(f_center_Hz, Q, f_sample_Hz)?;
+    Ok(normalize(
+        alpha,
+        0f32,
+        -alpha,
+        1f32 + alpha,
+        -2f32 * cos_w,
+        1f32 - alpha,
+    ))
+}
+
+/// second order notch, blocking the center frequency
+pub fn notch(f_center_Hz: f32, Q: f32, f_sample_Hz: f32) -> Result<Coefficients, Error> {
+    let (cos_w, alpha) = prewarp(f_center_Hz, Q, f_sample_Hz)?;
+    Ok(notch_prewarped(cos_w, alpha))
+}
+
+/// second order notch from cos(ω) and alpha = sin(ω) / 2Q, without any checks
+pub(crate) fn notch_prewarped(cos_w: f32, alpha: f32) -> Coefficients {
+    normalize(
+        1f32,
+        -2f32 * cos_w,
+        1f32,
+        1f32 + alpha,
+        -2f32 * cos_w,
+        1f32 - alpha,
+    )
+}
+
+/// cos(ω) and alpha = sin(ω) / 2Q of a frequency
+fn prewarp(f_Hz: f32, Q: f32, f_sample_Hz: f32) -> Result<(f32, f32), Error> {
+    if !(f_Hz > 0f32 && f_Hz < f_sample_Hz / 2f32) {
+        return Err(Error::InvalidFrequency);
+    }
+    if !Q.is_finite() || Q <= 0f32 {
+        return Err(Error::InvalidQ);
+    }
+    let w = 2f32 * PI * f_Hz / f_sample_Hz;
+    Ok((w.cos(), w.sin() / (2f32 * Q)))
+}
+
+/// coefficients divided by a0
+fn normalize(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Coefficients {
+    Coefficients {
+        b0: b0 / a0,
+        b1: b1 / a0,
+        b2: b2 / a0,
+        a1: a1 / a0,
+        a2: a2 / a0,
+    }
+}
+
+/// biquad filter state in transposed direct form II
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Biquad {
+    /// first state
+    s1: f32,
+    /// second state
+    s2: f32,
+    /// filter coefficients
+    coefficients: Coefficients,
+}
+
+impl Biquad {
+    /// create new biquad from coefficients
+    pub fn new(coefficients: Coefficients) -> Biquad {
+        Biquad {
+            s1: 0f32,
+            s2: 0f32,
+            coefficients,
+        }
+    }
+
+    /// update filter with new data
+    pub fn update(&mut self, input: f32) -> f32 {
+        let c = &self.coefficients;
+        let output = c.b0 * input + self.s1;
+        self.s1 = c.b1 * input - c.a1 * output + self.s2;
+        self.s2 = c.b2 * input - c.a2 * output;
+        output
+    }
+
+    /// replace coefficients at runtime. The states are kept.
+    pub fn set_coefficients(&mut self, coefficients: Coefficients) {
+        self.coefficients = coefficients;
+    }
+
+    /// filter coefficients
+    pub fn coefficients(&self) -> Coefficients {
+        self.coefficients
+    }
+
+    /// reset filter to a constant input, so the output starts settled at its DC response
+    pub fn reset(&mut self, input: f32) {
+        let output = input * self.dc_gain();
+        let c = &self.coefficients;
+        self.s2 = c.b2 * input - c.a2 * output;
+        self.s1 = c.b1 * input - c.a1 * output + self.s2;
+    }
+
+    /// gain for constant inputs
+    fn dc_gain(&self) -> f32 {
+        let c = &self.coefficients;
+        (c.b0 + c.b1 + c.b2) / (1f32 + c.a1 + c.a2)
+    }
+}
+
+/// cascade of biquad sections, run one after another
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Cascade<const N: usize> {
+    /// filter sections
+    sections: [Biquad; N],
+}
+
+impl<const N: usize> Cascade<N> {
+    /// create new cascade from coefficients of each section
+    pub fn new(coefficients: [Coefficients; N]) -> Cascade<N> {
+        Cascade {
+            sections: coefficients.map(Biquad::new),
+        }
+    }
+
+    /// update all sections with new data
+    pub fn update(&mut self, input: f32) -> f32 {
+        self.sections
+            .iter_mut()
+            .fold(input, |signal, section| section.update(signal))
+    }
+
+    /// filter sections, e.g. to change coefficients
+    pub fn sections(&mut self) -> &mut [Biquad; N] {
+        &mut self.sections
+    }
+
+    /// reset all sections to a constant input
+    pub fn reset(&mut self, input: f32) {
+        let mut signal = input;
+        for section in self.sections.iter_mut() {
+            section.reset(signal);
+            signal *= section.dc_gain();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const F_SAMPLE: f32 = 10000f32;
+
+    /// amplitude of the settled response to a sine
+    fn gain(filter: &mut impl FnMut(f32) -> f32, f_Hz: f32) -> f32 {
+        let mut amplitude = 0f32;
+        for n in 0..20000 {
+            let output = filter((2f32 * PI * f_Hz * n as f32 / F_SAMPLE).sin());
+            if n > 10000 {
+                amplitude = amplitude.max(output.abs());
+            }
+        }
+        amplitude
+    }
+
+    fn gain_of(coefficients: Coefficients, f_Hz: f32) -> f32 {
+        let mut biquad = Biquad::new(coefficients);
+        gain(&mut |x| biquad.update(x), f_Hz)
+    }
+
+    #[test]
+    fn designs() {
+        let Q = 1f32 / 2f32.sqrt();
+        let lp = lowpass(100f32, Q, F_SAMPLE).unwrap();
+        assert!(float_cmp::approx_eq!(
+            f32,
+            gain_of(lp, 10f32),
+            1f32,
+            epsilon = 0.01
+        ));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            gain_of(lp, 100f32),
+            Q,
+            epsilon = 0.01
+        ));
+        assert!(gain_of(lp, 1000f32) < 0.011f32);
+
+        let hp = highpass(100f32, Q, F_SAMPLE).unwrap();
+        assert!(gain_of(hp, 10f32) < 0.011f32);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            gain_of(hp, 100f32),
+            Q,
+            epsilon = 0.01
+        ));
+
+        let bp = bandpass(500f32, 5f32, F_SAMPLE).unwrap();
+        assert!(float_cmp::approx_eq!(
+            f32,
+            gain_of(bp, 500f32),
+            1f32,
+            epsilon = 0.01
+        ));
+        assert!(gain_of(bp, 100f32) < 0.1f32);
+
+        let n = notch(500f32, 5f32, F_SAMPLE).unwrap();
+        assert!(gain_of(n, 500f32) < 0.01f32);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            gain_of(n, 100f32),
+            1f32,
+            epsilon = 0.02
+        ));
+
+        assert_eq!(lowpass(6000f32, Q, F_SAMPLE), Err(Error::InvalidFrequency));
+        assert_eq!(notch(500f32, 0f32, F_SAMPLE), Err(Error::InvalidQ));
+    }
+
+    #[test]
+    fn cascade() {
+        let Q = 1f32 / 2f32.sqrt();
+        let lp = lowpass(100f32, Q, F_SAMPLE).unwrap();
+        let mut cascade = Cascade::new([lp, lp]);
+        // fourth order: twice the attenuation in dB
+        let attenuation = gain(&mut |x| cascade.update(x), 1000f32);
+        assert!(attenuation < 1.5e-4f32);
+
+        // reset to a settled DC level
+        cascade.reset(2f32);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            cascade.update(2f32),
+            2f32,
+            epsilon = 1e-4
+        ));
+    }
+}
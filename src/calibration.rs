@@ -0,0 +1,368 @@
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+#![allow(non_snake_case)]
+
+//! angle sensor offset calibration against the electrical frame
+//!
+//! However carefully you mount a hall sensor board or an encoder, its zero won't sit on the rotor's
+//! d axis. And which way it counts and how many electrical revolutions make one sensor revolution
+//! is something worth checking before trusting it with a current controller.
+//!
+//! The calibration locks the rotor with a d current at a number of electrical angles, first
+//! forwards, then backwards, and samples the sensor angle at each of them once the rotor settled.
+//! From this it calculates:
+//!
+//! - direction: does the sensor count up while the electrical angle goes up?
+//! - pole pairs: electrical angle travelled per sensor angle, checked against the configured value
+//! - offset: the electrical angle at sensor zero, averaged over all positions. Going both ways
+//!   cancels out the lag caused by friction.
+//!
+//! The resulting [CalibrationResult] converts sensor angles into electrical angles. Use a pole pair
+//! count of 1 for hall sensors, whose angle is electrical already, and the motor's pole pairs for
+//! mechanical angles like the ones of [crate::encoder::Encoder].
+
+use crate::motor::{wrap_angle, wrap_angle_diff};
+use core::f32::consts::PI;
+#[cfg_attr(test, allow(unused_imports))]
+use num_traits::Float;
+
+/// calibration errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// sensor angle didn't change while the rotor was turned
+    NoMovement,
+    /// measured pole pairs differ from the configured pole pairs beyond tolerance
+    PolePairMismatch,
+    /// no steps, revolutions or pole pairs, or settle time or sampling frequency not positive and
+    /// finite
+    InvalidConfig,
+}
+
+/// configuration struct for calibration construction
+#[derive(PartialEq, Debug)]
+pub struct CalibrationConfig {
+    /// d current to lock the rotor with
+    pub current: f32,
+    /// number of lock positions per direction
+    pub steps: u16,
+    /// electrical revolutions per direction
+    pub revolutions: u8,
+    /// time in seconds for the rotor to settle on every lock position
+    pub t_settle: f32,
+    /// electrical revolutions per sensor revolution. 1 for hall sensors
+    pub pole_pairs: u8,
+    /// relative deviation of the measured pole pairs that is still accepted
+    pub pole_pair_tolerance: f32,
+}
+
+/// output of a calibration step
+#[derive(PartialEq, Debug)]
+pub enum Output {
+    /// lock the rotor by applying the d current at this electrical angle
+    Lock {
+        /// electrical angle of the d axis in rad
+        angle: f32,
+        /// d current
+        current: f32,
+    },
+    /// calibration finished, current can be switched off
+    Done(CalibrationResult),
+}
+
+/// result of a calibration
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct CalibrationResult {
+    /// electrical angle in rad at sensor angle 0
+    pub offset: f32,
+    /// sensor counts opposite to the electrical angle
+    pub inverted: bool,
+    /// electrical revolutions per sensor revolution as configured
+    pub pole_pairs: u8,
+    /// electrical revolutions per sensor revolution as measured
+    pub pole_pairs_measured: f32,
+}
+
+impl CalibrationResult {
+    /// convert a sensor angle into an electrical angle from 0 to 2π
+    pub fn electrical_angle(&self, sensor_angle: f32) -> f32 {
+        let direction = if self.inverted { -1f32 } else { 1f32 };
+        let angle = direction * self.pole_pairs as f32 * sensor_angle + self.offset;
+        wrap_angle(angle)
+    }
+}
+
+/// calibration state machine
+#[derive(PartialEq, Debug)]
+pub struct Calibration {
+    /// lock position counter over both directions
+    step: u32,
+    /// time since lock position was applied
+    t_step: f32,
+    /// sensor angle at the first lock position
+    sensor_start: f32,
+    /// most recent sensor angle
+    sensor_recent: f32,
+    /// sensor angle travelled since first lock position, unwrapped
+    sensor_travel: f32,
+    /// sensor travel in forward direction
+    sensor_travel_forward: f32,
+    /// sums of the offset samples as cos and sin, for normal and inverted sensor direction. Both
+    /// are summed up, since the direction is only known at the end.
+    offset_sums: [(f32, f32); 2],
+    /// sample time
+    t_sample: f32,
+    /// configuration
+    config: CalibrationConfig,
+}
+
+impl Calibration {
+    /// create new calibration
+    pub fn new(cfg: CalibrationConfig, f_sample_Hz: f32) -> Result<Calibration, Error> {
+        let positive = |value: f32| value.is_finite() && value > 0f32;
+        if cfg.steps == 0
+            || cfg.revolutions == 0
+            || cfg.pole_pairs == 0
+            || !positive(cfg.t_settle)
+            || !positive(f_sample_Hz)
+        {
+            return Err(Error::InvalidConfig);
+        }
+        Ok(Calibration {
+            step: 0,
+            t_step: 0f32,
+            sensor_start: 0f32,
+            sensor_recent: 0f32,
+            sensor_travel: 0f32,
+            sensor_travel_forward: 0f32,
+            offset_sums: [(0f32, 0f32); 2],
+            t_sample: 1f32 / f_sample_Hz,
+            config: cfg,
+        })
+    }
+
+    /// run this method on every sample with the sensor angle in rad. Apply the returned lock
+    /// position until calibration is done.
+    pub fn update(&mut self, sensor_angle: f32) -> Result<Output, Error> {
+        let steps = self.config.steps as u32;
+
+        // track sensor travel over multiple revolutions
+        if self.step == 0 && self.t_step == 0f32 {
+            self.sensor_recent = sensor_angle;
+        }
+        self.sensor_travel += wrap_angle_diff(sensor_angle - self.sensor_recent);
+        self.sensor_recent = sensor_angle;
+
+        self.t_step += self.t_sample;
+        if self.t_step >= self.config.t_settle {
+            self.t_step = 0f32;
+            self.sample(self.lock_angle(), sensor_angle);
+            self.step += 1;
+            if self.step > 2 * steps {
+                return self.result().map(Output::Done);
+            }
+        }
+
+        Ok(Output::Lock {
+            angle: wrap_angle(self.lock_angle()),
+            current: self.config.current,
+        })
+    }
+
+    /// unwrapped electrical lock angle of the current step. Step 0 aligns the rotor, then it's
+    /// turned forwards and back to the start again.
+    fn lock_angle(&self) -> f32 {
+        let steps = self.config.steps as u32;
+        let position = if self.step <= steps {
+            self.step
+        } else {
+            2 * steps - self.step
+        };
+        position as f32 * 2f32 * PI * self.config.revolutions as f32 / steps as f32
+    }
+
+    /// take sample of settled lock position
+    fn sample(&mut self, lock_angle: f32, sensor_angle: f32) {
+        let steps = self.config.steps as u32;
+        if self.step == 0 {
+            // the rotor jumped here from anywhere, so only use it as start of travel
+            self.sensor_start = self.sensor_travel;
+            return;
+        }
+        if self.step == steps {
+            self.sensor_travel_forward = self.sensor_travel - self.sensor_start;
+        }
+        let pole_pairs = self.config.pole_pairs as f32;
+        let offset = lock_angle - pole_pairs * sensor_angle;
+        self.offset_sums[0].0 += offset.cos();
+        self.offset_sums[0].1 += offset.sin();
+        let offset = lock_angle + pole_pairs * sensor_angle;
+        self.offset_sums[1].0 += offset.cos();
+        self.offset_sums[1].1 += offset.sin();
+    }
+
+    /// calculate result of finished calibration
+    fn result(&self) -> Result<CalibrationResult, Error> {
+        let travel_electrical = 2f32 * PI * self.config.revolutions as f32;
+        // less than a tenth of the expected movement
+        let travel_expected = travel_electrical / self.config.pole_pairs as f32;
+        if self.sensor_travel_forward.abs() < 0.1f32 * travel_expected {
+            return Err(Error::NoMovement);
+        }
+
+        let inverted = self.sensor_travel_forward < 0f32;
+        let pole_pairs_measured = travel_electrical / self.sensor_travel_forward.abs();
+        let pole_pairs = self.config.pole_pairs as f32;
+        if (pole_pairs_measured - pole_pairs).abs() > self.config.pole_pair_tolerance * pole_pairs {
+            return Err(Error::PolePairMismatch);
+        }
+
+        let (re, im) = self.offset_sums[inverted as usize];
+
+        Ok(CalibrationResult {
+            offset: wrap_angle(im.atan2(re)),
+            inverted,
+            pole_pairs: self.config.pole_pairs,
+            pole_pairs_measured,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const F_SAMPLE: f32 = 1000f32;
+
+    fn config(pole_pairs: u8) -> CalibrationConfig {
+        CalibrationConfig {
+            current: 5f32,
+            steps: 12,
+            revolutions: 2,
+            t_settle: 0.05f32,
+            pole_pairs,
+            pole_pair_tolerance: 0.1f32,
+        }
+    }
+
+    /// run calibration on a rotor that follows the lock angle with a lag. Sensor has an offset,
+    /// direction and pole pairs.
+    fn run(
+        calibration: &mut Calibration,
+        offset: f32,
+        inverted: bool,
+        pole_pairs: f32,
+    ) -> Result<CalibrationResult, Error> {
+        let direction = if inverted { -1f32 } else { 1f32 };
+        // unwrapped electrical angles
+        let mut rotor_angle = 0f32;
+        let mut lock_angle = 0f32;
+        loop {
+            let sensor_angle = wrap_angle(direction * (rotor_angle - offset) / pole_pairs);
+            match calibration.update(sensor_angle)? {
+                Output::Lock { angle, current } => {
+                    assert_eq!(current, 5f32);
+                    // friction makes the rotor lag behind in direction of movement
+                    let diff = wrap_angle_diff(angle - lock_angle);
+                    if diff.abs() > 1e-3f32 {
+                        lock_angle += diff;
+                        rotor_angle = lock_angle - 0.05f32 * diff.signum();
+                    }
+                }
+                Output::Done(result) => return Ok(result),
+            }
+        }
+    }
+
+    #[test]
+    fn encoder_offset() {
+        let mut calibration = Calibration::new(config(4), F_SAMPLE).unwrap();
+        let result = run(&mut calibration, 1f32, false, 4f32).unwrap();
+
+        assert!(!result.inverted);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            result.pole_pairs_measured,
+            4f32,
+            epsilon = 0.1
+        ));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            result.offset,
+            1f32,
+            epsilon = 0.01
+        ));
+        // sensor angle converts back to electrical
+        assert!(float_cmp::approx_eq!(
+            f32,
+            result.electrical_angle(0.25f32),
+            2f32,
+            epsilon = 1e-3
+        ));
+    }
+
+    #[test]
+    fn hall_inverted() {
+        let mut calibration = Calibration::new(config(1), F_SAMPLE).unwrap();
+        let result = run(&mut calibration, 4f32, true, 1f32).unwrap();
+
+        assert!(result.inverted);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            result.offset,
+            4f32,
+            epsilon = 0.01
+        ));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            result.electrical_angle(0.5f32),
+            3.5f32,
+            epsilon = 1e-3
+        ));
+    }
+
+    #[test]
+    fn pole_pair_mismatch() {
+        let mut calibration = Calibration::new(config(2), F_SAMPLE).unwrap();
+        let result = run(&mut calibration, 0f32, false, 4f32);
+        assert_eq!(result, Err(Error::PolePairMismatch));
+    }
+
+    #[test]
+    fn no_movement() {
+        let mut calibration = Calibration::new(config(2), F_SAMPLE).unwrap();
+        let result = loop {
+            match calibration.update(0.3f32) {
+                Ok(Output::Lock { .. }) => (),
+                Ok(Output::Done(result)) => break Ok(result),
+                Err(e) => break Err(e),
+            }
+        };
+        assert_eq!(result, Err(Error::NoMovement));
+    }
+
+    #[test]
+    fn invalid_config() {
+        let invalid = [
+            CalibrationConfig {
+                steps: 0,
+                ..config(4)
+            },
+            CalibrationConfig {
+                revolutions: 0,
+                ..config(4)
+            },
+            config(0),
+            CalibrationConfig {
+                t_settle: 0f32,
+                ..config(4)
+            },
+        ];
+        for cfg in invalid {
+            assert_eq!(Calibration::new(cfg, F_SAMPLE), Err(Error::InvalidConfig));
+        }
+        assert_eq!(
+            Calibration::new(config(4), f32::INFINITY),
+            Err(Error::InvalidConfig)
+        );
+    }
+}
@@ -0,0 +1,290 @@
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+#![allow(non_snake_case)]
+
+//! complex vector current controller in dq
+//!
+//! In the rotor frame a motor looks like this:
+//!
+//! V = R I + L dI/dt + jω L I + jω ψ
+//!
+//! The jω L I part couples d and q: change one current and the other one gets a kick, and the
+//! faster the rotor spins, the harder. Two independent PI controllers for d and q don't know about
+//! each other and start fighting as soon as the electrical frequency gets into the range of the
+//! sample rate.
+//!
+//! [CurrentController] treats the dq current as one complex value and offers two ways of dealing
+//! with the coupling:
+//!
+//! - feedforward: the coupling voltage is calculated from the measured current and added to the
+//!   output. Simple, but it's only as good as the measurement and late by a sample.
+//! - complex vector: the controller's zero is placed right on the motor's complex pole, so the
+//!   coupling is cancelled inside the loop. The current then follows with the same bandwidth at
+//!   any speed.
+//!
+//! Both add the induced voltage jω ψ as feedforward.
+//!
+//! The PI gains follow from the bandwidth: K_p = L ω_b and K_i = R ω_b. With anisotropic motors,
+//! the d axis uses L_d (the real part of the inductance), the q axis L_q (the imaginary part).
+//!
+//! The output is limited to a circle, not to a square. When the voltage vector is too long it's
+//! shortened, but keeps its direction. While limited, the integrator stops and is shortened to the
+//! limit itself, so it doesn't wind up.
+
+use crate::motor::Config;
+use num::complex::c32;
+use num::Complex;
+#[cfg_attr(test, allow(unused_imports))]
+use num_traits::Float;
+
+/// current controller errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// bandwidth, voltage limit or sampling frequency is not positive and finite
+    InvalidConfig,
+}
+
+/// decoupling of the d and q axes
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Decoupling {
+    /// no decoupling apart from the induced voltage
+    None,
+    /// coupling voltage from the measured current added to the output
+    Feedforward,
+    /// coupling cancelled by the complex vector controller zero
+    ComplexVector,
+}
+
+/// configuration struct for current controller construction
+#[derive(PartialEq, Debug)]
+pub struct CurrentControllerConfig {
+    /// bandwidth of the current loop in rad per second
+    pub bandwidth: f32,
+    /// maximum length of the output voltage vector
+    pub voltage_max: f32,
+    /// decoupling of the d and q axes
+    pub decoupling: Decoupling,
+}
+
+/// complex vector PI current controller state
+#[derive(PartialEq, Debug)]
+pub struct CurrentController {
+    /// integrator state as voltage in dq
+    integrator: Complex<f32>,
+    /// most recent output voltage in dq
+    output: Complex<f32>,
+    /// sample time
+    t_sample: f32,
+    /// configuration
+    config: CurrentControllerConfig,
+}
+
+impl CurrentController {
+    /// create new current controller
+    pub fn new(cfg: CurrentControllerConfig, f_sample_Hz: f32) -> Result<CurrentController, Error> {
+        if [cfg.bandwidth, cfg.voltage_max, f_sample_Hz]
+            .iter()
+            .any(|value| !value.is_finite() || *value <= 0f32)
+        {
+            return Err(Error::InvalidConfig);
+        }
+        Ok(CurrentController {
+            integrator: c32(0f32, 0f32),
+            output: c32(0f32, 0f32),
+            t_sample: 1f32 / f_sample_Hz,
+            config: cfg,
+        })
+    }
+
+    /// run this method on every sample with the current reference and the measured current in
+    /// dq, and the electrical speed in rad per second. Returns the voltage to apply in dq.
+    pub fn update(
+        &mut self,
+        motor: &Config,
+        i_ref: Complex<f32>,
+        i_dq: Complex<f32>,
+        speed: f32,
+    ) -> Complex<f32> {
+        let error = i_ref - i_dq;
+        let w_b = self.config.bandwidth;
+        let L = motor.inductance;
+
+        // proportional part with K_p = L ω_b per axis
+        let proportional = c32(L.re * w_b * error.re, L.im * w_b * error.im);
+
+        // integral part with K_i = R ω_b
+        let mut integral_input = error * motor.resistance * w_b;
+        if self.config.decoupling == Decoupling::ComplexVector {
+            // zero at the motor's complex pole: jω K_p
+            integral_input += coupling(proportional, speed, c32(1f32, 1f32));
+        }
+        self.integrator += integral_input * self.t_sample;
+
+        // induced voltage, and coupling from the measured current if asked for
+        let mut feedforward = c32(0f32, speed * motor.flux);
+        if self.config.decoupling == Decoupling::Feedforward {
+            feedforward += coupling(i_dq, speed, L);
+        }
+
+        // circular limit, keeping the direction
+        let output = proportional + self.integrator + feedforward;
+        let output_abs = output.norm_sqr().sqrt();
+        self.output = if output_abs > self.config.voltage_max {
+            // anti windup: stop integrating and keep the integrator itself within the limit
+            self.integrator -= integral_input * self.t_sample;
+            let held = self.integrator + feedforward;
+            let held_abs = held.norm_sqr().sqrt();
+            if held_abs > self.config.voltage_max {
+                self.integrator = held * (self.config.voltage_max / held_abs) - feedforward;
+            }
+            output * (self.config.voltage_max / output_abs)
+        } else {
+            output
+        };
+
+        self.output
+    }
+
+    /// most recent output voltage in dq
+    pub fn output(&self) -> Complex<f32> {
+        self.output
+    }
+
+    /// reset integrator to given voltage in dq. If in doubt, use 0.
+    pub fn reset(&mut self, integrator: Complex<f32>) {
+        self.integrator = integrator;
+        self.output = c32(0f32, 0f32);
+    }
+}
+
+/// coupling voltage jω L x with inductance L_d on the real and L_q on the imaginary part
+fn coupling(x: Complex<f32>, speed: f32, L: Complex<f32>) -> Complex<f32> {
+    c32(-speed * L.im * x.im, speed * L.re * x.re)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const F_SAMPLE: f32 = 10000f32;
+
+    fn motor_config() -> Config {
+        Config {
+            resistance: 0.1f32,
+            inductance: c32(1e-4f32, 1.5e-4f32),
+            flux: 0.01f32,
+            inertia: 1e-4f32,
+        }
+    }
+
+    /// step the q current at high electrical speed. Returns the highest d current and the final
+    /// current.
+    fn step(decoupling: Decoupling, voltage_max: f32) -> (f32, Complex<f32>) {
+        let motor = motor_config();
+        let mut controller = CurrentController::new(
+            CurrentControllerConfig {
+                bandwidth: 2000f32,
+                voltage_max,
+                decoupling,
+            },
+            F_SAMPLE,
+        )
+        .unwrap();
+
+        let speed = 3000f32;
+        let i_ref = c32(0f32, 10f32);
+        let mut i_dq = c32(0f32, 0f32);
+        let mut i_d_max = 0f32;
+        for _ in 0..100 {
+            let v_dq = controller.update(&motor, i_ref, i_dq, speed);
+            assert!(v_dq.norm_sqr().sqrt() <= voltage_max + 1e-4f32);
+            // integrate motor over the sample period
+            let substeps = 100;
+            let dt = 1f32 / F_SAMPLE / substeps as f32;
+            for _ in 0..substeps {
+                let v_ind = c32(0f32, speed * motor.flux);
+                let v_L = v_dq
+                    - i_dq * motor.resistance
+                    - coupling(i_dq, speed, motor.inductance)
+                    - v_ind;
+                i_dq += c32(v_L.re / motor.inductance.re, v_L.im / motor.inductance.im) * dt;
+            }
+            i_d_max = i_d_max.max(i_dq.re.abs());
+        }
+        (i_d_max, i_dq)
+    }
+
+    #[test]
+    fn decoupling() {
+        let (i_d_none, _) = step(Decoupling::None, 100f32);
+        let (i_d_ff, i_ff) = step(Decoupling::Feedforward, 100f32);
+        let (i_d_cv, i_cv) = step(Decoupling::ComplexVector, 100f32);
+
+        for i_dq in [i_ff, i_cv] {
+            assert!(float_cmp::approx_eq!(f32, i_dq.im, 10f32, epsilon = 0.1));
+            assert!(float_cmp::approx_eq!(f32, i_dq.re, 0f32, epsilon = 0.1));
+        }
+        // the d axis barely notices the q step with decoupling
+        assert!(i_d_ff < 0.2f32 * i_d_none);
+        assert!(i_d_cv < 0.2f32 * i_d_none);
+    }
+
+    #[test]
+    fn circular_limit() {
+        let motor = Config {
+            inductance: c32(1e-4f32, 1e-4f32),
+            ..motor_config()
+        };
+        let mut controller = CurrentController::new(
+            CurrentControllerConfig {
+                bandwidth: 2000f32,
+                voltage_max: 10f32,
+                decoupling: Decoupling::ComplexVector,
+            },
+            F_SAMPLE,
+        )
+        .unwrap();
+
+        // large error in both axes: output is limited, but keeps its direction
+        let error = c32(-300f32, 400f32);
+        let v_dq = controller.update(&motor, error, c32(0f32, 0f32), 0f32);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            v_dq.norm_sqr().sqrt(),
+            10f32,
+            epsilon = 1e-4
+        ));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            v_dq.im.atan2(v_dq.re),
+            error.im.atan2(error.re),
+            epsilon = 1e-2
+        ));
+
+        // integrator doesn't wind up while saturated
+        for _ in 0..1000 {
+            controller.update(&motor, error, c32(0f32, 0f32), 0f32);
+        }
+        let v_dq = controller.update(&motor, c32(0f32, 0f32), c32(0f32, 0f32), 0f32);
+        assert!(v_dq.norm_sqr().sqrt() < 1f32);
+    }
+
+    #[test]
+    fn invalid_config() {
+        let cfg = |bandwidth: f32, voltage_max: f32| CurrentControllerConfig {
+            bandwidth,
+            voltage_max,
+            decoupling: Decoupling::ComplexVector,
+        };
+        for (bandwidth, voltage_max) in [(0f32, 10f32), (2000f32, 0f32), (2000f32, f32::NAN)] {
+            assert_eq!(
+                CurrentController::new(cfg(bandwidth, voltage_max), F_SAMPLE),
+                Err(Error::InvalidConfig)
+            );
+        }
+        assert_eq!(
+            CurrentController::new(cfg(2000f32, 10f32), 0f32),
+            Err(Error::InvalidConfig)
+        );
+    }
+}
@@ -6,6 +6,7 @@
 //! [clarke](https://en.wikipedia.org/wiki/Alpha%E2%80%93beta_transformation)
 //! transformations and inverses
 
+#[cfg_attr(test, allow(unused_imports))]
 use micromath::F32Ext;
 use num::{complex::c32, Complex};
 
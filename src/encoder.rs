@@ -0,0 +1,414 @@
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+#![allow(non_snake_case)]
+
+//! quadrature incremental encoder rotor estimator
+//!
+//! ABZ encoders emit two square waves A and B shifted by 90° and an index pulse Z once per
+//! revolution. Most micro controllers have timers that decode A and B in hardware into a counter
+//! with 4 counts per line, so all we get to see is a timer count that wraps around at its bit width.
+//!
+//! Speed can be calculated from this count in two ways:
+//!
+//! 1. frequency method: count the edges within a fixed sample time. Great at high speeds, but at
+//!    low speeds only a couple of counts are seen per sample and the result is jumpy.
+//! 2. period method: measure the time between two edges with a capture timer. Great at low speeds,
+//!    but at high speeds the edges come faster than the timer resolution.
+//!
+//! [Encoder] uses the period method below a configured speed and the frequency method above,
+//! with a hysteresis to keep it from toggling.
+//!
+//! The index pulse marks the mechanical zero. When it arrives, the count is aligned to it, and from
+//! the second index pulse on, the counts in between are checked for a full revolution.
+
+use crate::motor::Mechanical;
+use core::f32::consts::PI;
+
+/// encoder errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// less counts than a full revolution between two index pulses
+    MissingCounts(u32),
+    /// more counts than a full revolution between two index pulses
+    ExtraCounts(u32),
+    /// counter bits not within 1 to 32, no counts per revolution or pole pairs, or sampling
+    /// frequency not positive and finite
+    InvalidConfig,
+}
+
+/// speed estimation method
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SpeedMethod {
+    /// time between two encoder edges
+    Period,
+    /// encoder edges per sample time
+    Frequency,
+}
+
+/// configuration struct for encoder construction
+#[derive(PartialEq, Debug)]
+pub struct EncoderConfig {
+    /// counts per mechanical revolution, 4 times the encoder lines for quadrature decoding
+    pub counts_per_revolution: u32,
+    /// bit width of the timer counter from 1 to 32, 16 or 32 for most timers
+    pub counter_bits: u8,
+    /// pole pairs of the motor to convert mechanical to electrical angle
+    pub pole_pairs: u8,
+    /// mechanical speed in rad per second to switch from period to frequency method
+    pub speed_threshold: f32,
+    /// speed hysteresis in rad per second around the switching threshold
+    pub speed_hysteresis: f32,
+    /// deviation in counts at index pulse still considered fine
+    pub index_tolerance: u32,
+}
+
+/// quadrature encoder rotor state estimation struct
+#[derive(PartialEq, Debug)]
+pub struct Encoder {
+    /// most recent timer count
+    count_recent: u32,
+    /// counts within a revolution from 0 to counts_per_revolution
+    position: u32,
+    /// direction of the most recent count change, 1 or -1
+    direction: i32,
+    /// index pulse has been seen at least once
+    index_found: bool,
+    /// time between the two most recent encoder edges
+    t_edge_period: f32,
+    /// time since the most recent encoder edge
+    t_edge: f32,
+    /// speed estimation method in use
+    speed_method: SpeedMethod,
+    /// most recent mechanical state
+    state: Mechanical,
+    /// sample time
+    t_sample: f32,
+    /// configuration
+    config: EncoderConfig,
+}
+
+impl Encoder {
+    /// create new encoder with the current timer count as starting position
+    pub fn new(cfg: EncoderConfig, count: u32, f_sample_Hz: f32) -> Result<Encoder, Error> {
+        if cfg.counter_bits == 0
+            || cfg.counter_bits > 32
+            || cfg.counts_per_revolution == 0
+            || cfg.pole_pairs == 0
+            || !f_sample_Hz.is_finite()
+            || f_sample_Hz <= 0f32
+        {
+            return Err(Error::InvalidConfig);
+        }
+        Ok(Encoder {
+            count_recent: count,
+            position: 0,
+            direction: 1,
+            index_found: false,
+            t_edge_period: f32::INFINITY,
+            t_edge: 0f32,
+            speed_method: SpeedMethod::Period,
+            state: Mechanical {
+                angle: 0f32,
+                speed: 0f32,
+                acceleration: 0f32,
+            },
+            t_sample: 1f32 / f_sample_Hz,
+            config: cfg,
+        })
+    }
+
+    /// run this method once per sample period with the current timer count. Returns the
+    /// mechanical rotor state.
+    pub fn update(&mut self, count: u32) -> Mechanical {
+        let delta = self.count_delta(count);
+        self.count_recent = count;
+        self.move_position(delta);
+        if delta != 0 {
+            self.direction = delta.signum();
+        }
+
+        let angle_per_count = self.angle_per_count();
+        let speed = match self.speed_method {
+            SpeedMethod::Frequency => delta as f32 * angle_per_count / self.t_sample,
+            SpeedMethod::Period => {
+                // without new edges the speed has to decay
+                let period = self.t_edge_period.max(self.t_edge);
+                self.direction as f32 * angle_per_count / period
+            }
+        };
+        self.t_edge += self.t_sample;
+
+        // switch speed method with hysteresis
+        if speed.abs() > self.config.speed_threshold + self.config.speed_hysteresis {
+            self.speed_method = SpeedMethod::Frequency;
+        } else if speed.abs() < self.config.speed_threshold - self.config.speed_hysteresis {
+            self.speed_method = SpeedMethod::Period;
+        }
+
+        self.state = Mechanical {
+            angle: self.position as f32 * angle_per_count,
+            speed,
+            acceleration: (speed - self.state.speed) / self.t_sample,
+        };
+
+        self.mechanical()
+    }
+
+    /// run this method on every encoder edge with the time between this and the previous edge,
+    /// usually from a capture timer
+    pub fn capture(&mut self, t_edge_period: f32) {
+        self.t_edge_period = t_edge_period;
+        self.t_edge = 0f32;
+    }
+
+    /// run this method on an index pulse with the timer count latched at the pulse. Aligns the
+    /// position to zero. From the second pulse on, the counts since the previous pulse are checked
+    /// for a full revolution. The position is aligned in any case.
+    pub fn index(&mut self, count: u32) -> Result<(), Error> {
+        let cpr = self.config.counts_per_revolution as i64;
+        let index_position =
+            (self.position as i64 + self.count_delta(count) as i64).rem_euclid(cpr);
+        // align position to index
+        self.position = (self.position as i64 - index_position).rem_euclid(cpr) as u32;
+
+        // deviation from zero within -cpr/2 to cpr/2
+        let deviation = if index_position > cpr / 2 {
+            index_position - cpr
+        } else {
+            index_position
+        };
+        let index_found = self.index_found;
+        self.index_found = true;
+        if !index_found || deviation.unsigned_abs() <= self.config.index_tolerance as u64 {
+            return Ok(());
+        }
+
+        let counts = deviation.unsigned_abs() as u32;
+        if deviation * (self.direction as i64) < 0 {
+            Err(Error::MissingCounts(counts))
+        } else {
+            Err(Error::ExtraCounts(counts))
+        }
+    }
+
+    /// index pulse has been seen since construction, so angles are absolute
+    pub fn index_found(&self) -> bool {
+        self.index_found
+    }
+
+    /// speed estimation method currently in use
+    pub fn speed_method(&self) -> SpeedMethod {
+        self.speed_method
+    }
+
+    /// most recent mechanical rotor state
+    pub fn mechanical(&self) -> Mechanical {
+        Mechanical { ..self.state }
+    }
+
+    /// most recent electrical rotor state
+    pub fn electrical(&self) -> Mechanical {
+        let pole_pairs = self.config.pole_pairs as f32;
+        Mechanical {
+            angle: (self.state.angle * pole_pairs) % (2f32 * PI),
+            speed: self.state.speed * pole_pairs,
+            acceleration: self.state.acceleration * pole_pairs,
+        }
+    }
+
+    /// mechanical angle of a single count
+    fn angle_per_count(&self) -> f32 {
+        2f32 * PI / self.config.counts_per_revolution as f32
+    }
+
+    /// signed count difference to most recent count, respecting timer wrap around
+    fn count_delta(&self, count: u32) -> i32 {
+        let bits = self.config.counter_bits as u32;
+        let delta = count.wrapping_sub(self.count_recent) as i64;
+        // shift up and back down to sign extend the counter width
+        ((delta << (64 - bits)) >> (64 - bits)) as i32
+    }
+
+    /// move position by counts within a revolution
+    fn move_position(&mut self, delta: i32) {
+        let cpr = self.config.counts_per_revolution as i64;
+        self.position = (self.position as i64 + delta as i64).rem_euclid(cpr) as u32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> EncoderConfig {
+        EncoderConfig {
+            counts_per_revolution: 4000,
+            counter_bits: 16,
+            pole_pairs: 4,
+            speed_threshold: 100f32,
+            speed_hysteresis: 10f32,
+            index_tolerance: 1,
+        }
+    }
+
+    #[test]
+    fn wrap_around() {
+        let mut enc = Encoder::new(config(), 65530, 1000f32).unwrap();
+
+        // forward over 16 bit overflow
+        let state = enc.update(10);
+        let angle = 16f32 * 2f32 * PI / 4000f32;
+        assert!(float_cmp::approx_eq!(
+            f32,
+            state.angle,
+            angle,
+            epsilon = 1e-5
+        ));
+
+        // backwards over zero
+        let state = enc.update(65526);
+        let angle = 3996f32 * 2f32 * PI / 4000f32;
+        assert!(float_cmp::approx_eq!(
+            f32,
+            state.angle,
+            angle,
+            epsilon = 1e-5
+        ));
+
+        // electrical angle is 4 times mechanical
+        let elec = enc.electrical();
+        assert!(float_cmp::approx_eq!(
+            f32,
+            elec.angle,
+            (angle * 4f32) % (2f32 * PI),
+            epsilon = 1e-4
+        ));
+    }
+
+    #[test]
+    fn speed_method() {
+        let mut enc = Encoder::new(config(), 0, 1000f32).unwrap();
+
+        // slow: 1 count every 10 ms
+        let mut count = 0u32;
+        for i in 0..100 {
+            if i % 10 == 0 {
+                count += 1;
+                enc.capture(10e-3f32);
+            }
+            enc.update(count);
+        }
+        let speed = 2f32 * PI / 4000f32 / 10e-3f32;
+        assert_eq!(enc.speed_method(), SpeedMethod::Period);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            enc.mechanical().speed,
+            speed,
+            epsilon = 0.01
+        ));
+
+        // fast: 100 counts per ms
+        for _ in 0..10 {
+            count += 100;
+            enc.capture(10e-6f32);
+            enc.update(count);
+        }
+        let speed = 2f32 * PI / 40f32 / 1e-3f32;
+        assert_eq!(enc.speed_method(), SpeedMethod::Frequency);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            enc.mechanical().speed,
+            speed,
+            epsilon = 0.01
+        ));
+
+        // standstill decays the period method
+        for _ in 0..1000 {
+            enc.update(count);
+        }
+        assert_eq!(enc.speed_method(), SpeedMethod::Period);
+        assert!(enc.mechanical().speed < 0.01f32);
+    }
+
+    #[test]
+    fn invalid_config() {
+        let cfg = |counter_bits: u8, counts_per_revolution: u32, pole_pairs: u8| EncoderConfig {
+            counter_bits,
+            counts_per_revolution,
+            pole_pairs,
+            ..config()
+        };
+        assert!(Encoder::new(cfg(32, 4000, 4), 0, 1000f32).is_ok());
+        assert_eq!(
+            Encoder::new(cfg(0, 4000, 4), 0, 1000f32),
+            Err(Error::InvalidConfig)
+        );
+        assert_eq!(
+            Encoder::new(cfg(33, 4000, 4), 0, 1000f32),
+            Err(Error::InvalidConfig)
+        );
+        assert_eq!(
+            Encoder::new(cfg(16, 0, 4), 0, 1000f32),
+            Err(Error::InvalidConfig)
+        );
+        assert_eq!(
+            Encoder::new(cfg(16, 4000, 0), 0, 1000f32),
+            Err(Error::InvalidConfig)
+        );
+        for f_sample in [0f32, -1000f32, f32::NAN] {
+            assert_eq!(
+                Encoder::new(config(), 0, f_sample),
+                Err(Error::InvalidConfig)
+            );
+        }
+
+        // 32 bit counter wraps around as well
+        let mut enc = Encoder::new(cfg(32, 4000, 4), u32::MAX - 1, 1000f32).unwrap();
+        let state = enc.update(2);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            state.angle,
+            4f32 * 2f32 * PI / 4000f32,
+            epsilon = 1e-5
+        ));
+    }
+
+    #[test]
+    fn index() {
+        let mut enc = Encoder::new(config(), 0, 1000f32).unwrap();
+
+        enc.update(100);
+        assert!(!enc.index_found());
+        // first index at count 150 defines the zero
+        assert_eq!(enc.index(150), Ok(()));
+        assert!(enc.index_found());
+        let state = enc.update(200);
+        let angle = 50f32 * 2f32 * PI / 4000f32;
+        assert!(float_cmp::approx_eq!(
+            f32,
+            state.angle,
+            angle,
+            epsilon = 1e-5
+        ));
+
+        // one full revolution later, index within tolerance
+        enc.update(4100);
+        assert_eq!(enc.index(4151), Ok(()));
+
+        // next revolution lost 5 counts
+        enc.update(8100);
+        assert_eq!(enc.index(8146), Err(Error::MissingCounts(5)));
+        // position was realigned anyway
+        let state = enc.update(8146);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            state.angle,
+            0f32,
+            epsilon = 1e-5
+        ));
+
+        // next revolution gained 7 counts
+        enc.update(12100);
+        assert_eq!(enc.index(12153), Err(Error::ExtraCounts(7)));
+    }
+}
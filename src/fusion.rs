@@ -0,0 +1,336 @@
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+#![allow(non_snake_case)]
+
+//! fusion of hall sensors and back-EMF pll observer
+//!
+//! Hall sensors and the [crate::pll::Pll] observer are good at opposite ends of the speed range.
+//! Halls work from standstill, but only know the angle exactly at their edges and have to
+//! interpolate in between. The observer is precise at speed, but blind while the induced voltage
+//! is small.
+//!
+//! [Fusion] switches between both by speed with hysteresis: the observer takes over once the hall
+//! speed rises above an upper speed, and hands back once it falls below a lower speed. In between,
+//! whichever source is dominant stays. On every switch the output fades from one source to the
+//! other over a configured time, so the angle doesn't jump.
+//!
+//! Without hall edges, a stalled rotor would keep its most recent hall speed forever. So once the
+//! interpolation reaches the next edge, the hall speed is cut down to what still fits 60° in the
+//! time since the most recent edge.
+//!
+//! Every hall edge is an exact angle measurement, so it's used to pull the observer angle towards
+//! it. If observer and hall edge disagree by more than a threshold while the observer should be
+//! trustworthy, one of them is broken and an error is returned instead.
+
+use crate::motor::{wrap_angle, wrap_angle_diff, Config, Mechanical};
+use crate::pll::Pll;
+use core::f32::consts::PI;
+use num::Complex;
+
+/// fusion errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// hall edge and observer angle differ by more than the threshold
+    Disagreement,
+}
+
+/// rotor state source
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Source {
+    /// interpolated hall sensor angle
+    Hall,
+    /// back-EMF pll observer
+    Observer,
+}
+
+/// configuration struct for fusion construction
+#[derive(PartialEq, Debug)]
+pub struct FusionConfig {
+    /// electrical speed in rad per second below which the hall sensors take over
+    pub speed_low: f32,
+    /// electrical speed in rad per second above which the observer takes over
+    pub speed_high: f32,
+    /// time in seconds to fade from one source to the other
+    pub crossfade_time: f32,
+    /// part of the angle error corrected in the observer on every hall edge, 0 to 1
+    pub correction_gain: f32,
+    /// angle difference in rad between hall edge and observer considered a fault
+    pub disagreement_threshold: f32,
+}
+
+/// hall and observer fusion state
+#[derive(PartialEq, Debug)]
+pub struct Fusion {
+    /// angle of the most recent hall edge
+    hall_edge_angle: f32,
+    /// hall angle interpolated since the most recent edge
+    hall_angle: f32,
+    /// speed of the most recent hall edge, cut down while no edge arrives
+    hall_speed: f32,
+    /// time since the most recent hall edge
+    t_edge: f32,
+    /// source currently dominating
+    dominant: Source,
+    /// weight of the observer from 0 to 1, fading towards the dominant source
+    weight: f32,
+    /// back-EMF observer
+    pll: Pll,
+    /// most recent fused speed for acceleration calculation
+    speed_recent: f32,
+    /// sample time
+    t_sample: f32,
+    /// configuration
+    config: FusionConfig,
+}
+
+impl Fusion {
+    /// create new fusion from observer, starting with hall sensors dominant
+    pub fn new(cfg: FusionConfig, pll: Pll, f_sample_Hz: f32) -> Fusion {
+        Fusion {
+            hall_edge_angle: 0f32,
+            hall_angle: 0f32,
+            hall_speed: 0f32,
+            t_edge: 0f32,
+            dominant: Source::Hall,
+            weight: 0f32,
+            pll,
+            speed_recent: 0f32,
+            t_sample: 1f32 / f_sample_Hz,
+            config: cfg,
+        }
+    }
+
+    /// run this method with the result of [crate::hall::Hall::interrupt_service_routine] on every
+    /// hall edge. Corrects the observer angle towards the hall edge.
+    pub fn hall_edge(&mut self, hall: &Mechanical) -> Result<(), Error> {
+        self.hall_edge_angle = hall.angle;
+        self.hall_angle = hall.angle;
+        self.hall_speed = hall.speed;
+        self.t_edge = 0f32;
+
+        let angle_diff = wrap_angle_diff(hall.angle - self.pll.angle());
+        if self.weight > 0f32 && angle_diff.abs() > self.config.disagreement_threshold {
+            return Err(Error::Disagreement);
+        }
+        self.pll
+            .correct_angle(self.config.correction_gain * angle_diff);
+
+        Ok(())
+    }
+
+    /// run this method on every sample with stator voltage and current in ab. Returns the fused
+    /// electrical rotor state.
+    pub fn update(&mut self, cfg: &Config, v_ab: Complex<f32>, i_ab: Complex<f32>) -> Mechanical {
+        let observer = self.pll.update(cfg, v_ab, i_ab);
+
+        // no edge within 60° at the most recent speed, so the rotor must be slower
+        self.t_edge += self.t_sample;
+        let speed_max = PI / 3f32 / self.t_edge;
+        if self.hall_speed > speed_max {
+            self.hall_speed = speed_max;
+        } else if self.hall_speed < -speed_max {
+            self.hall_speed = -speed_max;
+        }
+
+        // interpolate hall angle, but never beyond the next edge
+        let interpolation = wrap_angle_diff(
+            self.hall_angle + self.hall_speed * self.t_sample - self.hall_edge_angle,
+        )
+        .clamp(-PI / 3f32, PI / 3f32);
+        self.hall_angle = wrap_angle(self.hall_edge_angle + interpolation);
+
+        // dominant source with hysteresis
+        let speed_abs = self.hall_speed.abs();
+        if speed_abs >= self.config.speed_high {
+            self.dominant = Source::Observer;
+        } else if speed_abs <= self.config.speed_low {
+            self.dominant = Source::Hall;
+        }
+
+        // fade towards the dominant source
+        let weight_target = match self.dominant {
+            Source::Hall => 0f32,
+            Source::Observer => 1f32,
+        };
+        let fade_step = if self.config.crossfade_time > 0f32 {
+            self.t_sample / self.config.crossfade_time
+        } else {
+            1f32
+        };
+        self.weight += (weight_target - self.weight).clamp(-fade_step, fade_step);
+
+        let weight = self.weight;
+        let angle = wrap_angle(
+            self.hall_angle + weight * wrap_angle_diff(observer.angle - self.hall_angle),
+        );
+        let speed = self.hall_speed + weight * (observer.speed - self.hall_speed);
+        let acceleration = (speed - self.speed_recent) / self.t_sample;
+        self.speed_recent = speed;
+
+        Mechanical {
+            angle,
+            speed,
+            acceleration,
+        }
+    }
+
+    /// source currently dominating the fused rotor state
+    pub fn dominant(&self) -> Source {
+        self.dominant
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hall::Hall;
+    use crate::pll::tests::{motor_ab, motor_config};
+    use crate::pll::PllConfig;
+    use crate::six_step::angle_to_sector;
+    use num::complex::c32;
+
+    const F_SAMPLE: f32 = 10000f32;
+
+    /// hall sensor states of a sector
+    const SECTOR_TO_HALL: [(bool, bool, bool); 6] = [
+        (true, false, false),
+        (true, true, false),
+        (false, true, false),
+        (false, true, true),
+        (false, false, true),
+        (true, false, true),
+    ];
+
+    fn fusion() -> Fusion {
+        let pll = Pll::new(
+            PllConfig {
+                bandwidth: 200f32,
+                damping: 1f32,
+                speed_max: 5000f32,
+            },
+            F_SAMPLE,
+        )
+        .unwrap();
+        Fusion::new(
+            FusionConfig {
+                speed_low: 200f32,
+                speed_high: 400f32,
+                crossfade_time: 0.01f32,
+                correction_gain: 0.2f32,
+                disagreement_threshold: 0.3f32,
+            },
+            pll,
+            F_SAMPLE,
+        )
+    }
+
+    /// accelerate motor to speed within the first half of samples and hold it. Returns the angle
+    /// error of the last fused state.
+    fn run(fusion: &mut Fusion, speed_end: f32, samples: usize) -> f32 {
+        run_profile(
+            fusion,
+            |n| speed_end * (2f32 * n as f32 / samples as f32).min(1f32),
+            samples,
+        )
+    }
+
+    /// run motor with speed given by sample number. Returns the angle error of the last fused
+    /// state.
+    fn run_profile(fusion: &mut Fusion, speed_at: impl Fn(usize) -> f32, samples: usize) -> f32 {
+        let cfg = motor_config();
+        let mut angle = 0.1f32;
+        let mut sector = angle_to_sector(angle);
+        let (h1, h2, h3) = SECTOR_TO_HALL[sector as usize];
+        let mut hall = Hall::new(h1, h2, h3).unwrap();
+        let mut t_hall = 0f32;
+        let mut error = 0f32;
+
+        for n in 0..samples {
+            let speed = speed_at(n);
+            angle = wrap_angle(angle + speed / F_SAMPLE);
+            t_hall += 1f32 / F_SAMPLE;
+            if angle_to_sector(angle) != sector {
+                sector = angle_to_sector(angle);
+                let (h1, h2, h3) = SECTOR_TO_HALL[sector as usize];
+                let state = hall.interrupt_service_routine(h1, h2, h3, t_hall).unwrap();
+                fusion.hall_edge(&state).unwrap();
+                t_hall = 0f32;
+            }
+            let (v_ab, i_ab) = motor_ab(&cfg, angle, speed, 2f32);
+            let state = fusion.update(&cfg, v_ab, i_ab);
+            error = wrap_angle_diff(state.angle - angle);
+        }
+        error
+    }
+
+    #[test]
+    fn low_speed_hall() {
+        let mut fusion = fusion();
+        let error = run(&mut fusion, 100f32, 5000);
+        assert_eq!(fusion.dominant(), Source::Hall);
+        assert!(float_cmp::approx_eq!(f32, error, 0f32, epsilon = 0.05));
+    }
+
+    #[test]
+    fn high_speed_observer() {
+        let mut fusion = fusion();
+        let error = run(&mut fusion, 1000f32, 5000);
+        assert_eq!(fusion.dominant(), Source::Observer);
+        assert!(float_cmp::approx_eq!(f32, error, 0f32, epsilon = 0.02));
+    }
+
+    #[test]
+    fn disagreement() {
+        let mut fusion = fusion();
+        run(&mut fusion, 1000f32, 5000);
+
+        // hall edge far off the observer
+        let angle = wrap_angle(fusion.pll.angle() + 1f32);
+        let hall = Mechanical {
+            angle,
+            speed: 1000f32,
+            acceleration: 0f32,
+        };
+        assert_eq!(fusion.hall_edge(&hall), Err(Error::Disagreement));
+    }
+
+    #[test]
+    fn hysteresis() {
+        // within the band from below, halls stay in charge
+        let mut from_below = fusion();
+        run(&mut from_below, 300f32, 5000);
+        assert_eq!(from_below.dominant(), Source::Hall);
+        assert_eq!(from_below.weight, 0f32);
+
+        // within the band from above, the observer stays in charge
+        let mut from_above = fusion();
+        let error = run_profile(
+            &mut from_above,
+            |n| match n {
+                0..=2500 => 1000f32 * n as f32 / 2500f32,
+                2501..=5000 => 1000f32 - 700f32 * (n - 2500) as f32 / 2500f32,
+                _ => 300f32,
+            },
+            8000,
+        );
+        assert_eq!(from_above.dominant(), Source::Observer);
+        assert_eq!(from_above.weight, 1f32);
+        assert!(float_cmp::approx_eq!(f32, error, 0f32, epsilon = 0.02));
+    }
+
+    #[test]
+    fn stall() {
+        let mut fusion = fusion();
+        run(&mut fusion, 100f32, 5000);
+        let speed = fusion.hall_speed;
+
+        // rotor stops, no more hall edges
+        let cfg = motor_config();
+        let mut state = fusion.update(&cfg, c32(0f32, 0f32), c32(0f32, 0f32));
+        for _ in 0..10000 {
+            state = fusion.update(&cfg, c32(0f32, 0f32), c32(0f32, 0f32));
+        }
+        assert!(state.speed < 0.1f32 * speed);
+        assert!(state.speed <= PI / 3f32);
+    }
+}
@@ -0,0 +1,307 @@
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+#![allow(non_snake_case)]
+
+//! gain scheduled pid controller
+//!
+//! One set of gains rarely fits a motor over its whole operating range. The current loop sees a
+//! different plant at high speed, the speed loop a different one with a heavy load attached. So
+//! instead of finding the one compromise that works everywhere, tune the loop at a couple of
+//! operating points and let [GainScheduledPID] interpolate between them.
+//!
+//! The table of breakpoints has a fixed size given as const generic, so there's no allocation
+//! involved. Operating points outside of the table use the gains of its first or last breakpoint.
+//!
+//! Every gain change goes through [PID::set_config_bumpless], so the output doesn't jump when the
+//! operating point moves. The new gains only act on what comes next.
+
+use crate::pid::{self, AntiWindup, DerivativeFilter, DerivativeSource, PIDConfig, PID};
+
+/// gain schedule errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// table has no breakpoints
+    EmptyTable,
+    /// operating points are not strictly increasing or not finite
+    UnsortedBreakpoints,
+    /// K_p changes its sign within the table or the sampling frequency is invalid. K_p has to keep
+    /// its sign over the whole table, since bumpless gain changes can't pass a K_p of 0.
+    InvalidConfig(pid::Error),
+}
+
+/// interpolation between breakpoints
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Interpolation {
+    /// linear, gains change with a kink at every breakpoint
+    Linear,
+    /// smoothstep, gains change without kink at every breakpoint
+    Smooth,
+}
+
+/// pid configuration at an operating point
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Breakpoint {
+    /// value of the scheduling variable, like speed or inertia
+    pub operating_point: f32,
+    /// pid configuration to use at this operating point
+    pub config: PIDConfig,
+}
+
+/// configuration struct for gain scheduled pid construction
+#[derive(PartialEq, Debug)]
+pub struct GainScheduleConfig<const N: usize> {
+    /// breakpoints sorted by increasing operating point
+    pub breakpoints: [Breakpoint; N],
+    /// interpolation between breakpoints
+    pub interpolation: Interpolation,
+}
+
+impl<const N: usize> GainScheduleConfig<N> {
+    /// interpolated pid configuration at an operating point. All gains and limits are
+    /// interpolated.
+    pub fn config_at(&self, operating_point: f32) -> PIDConfig {
+        let first = &self.breakpoints[0];
+        if operating_point <= first.operating_point {
+            return first.config;
+        }
+
+        for window in self.breakpoints.windows(2) {
+            let (low, high) = (&window[0], &window[1]);
+            if operating_point < high.operating_point {
+                let x = (operating_point - low.operating_point)
+                    / (high.operating_point - low.operating_point);
+                let x = match self.interpolation {
+                    Interpolation::Linear => x,
+                    Interpolation::Smooth => x * x * (3f32 - 2f32 * x),
+                };
+                let lerp = |a: f32, b: f32| a + x * (b - a);
+                let (low_low, low_high) = low.config.limits();
+                let (high_low, high_high) = high.config.limits();
+                // interpolated configs of valid breakpoints are valid as well, only rounding of
+                // nearly equal limits could make them touch
+                return PIDConfig::new(
+                    lerp(low.config.K_p(), high.config.K_p()),
+                    lerp(low.config.K_i(), high.config.K_i()),
+                    lerp(low.config.K_d(), high.config.K_d()),
+                    (lerp(low_low, high_low), lerp(low_high, high_high)),
+                )
+                .unwrap_or(low.config);
+            }
+        }
+
+        self.breakpoints[N - 1].config
+    }
+
+    /// check for breakpoints, their order and the sign of K_p
+    fn validate(&self) -> Result<(), Error> {
+        if N == 0 {
+            return Err(Error::EmptyTable);
+        }
+        if self
+            .breakpoints
+            .iter()
+            .any(|breakpoint| !breakpoint.operating_point.is_finite())
+            || self
+                .breakpoints
+                .windows(2)
+                .any(|window| window[0].operating_point >= window[1].operating_point)
+        {
+            return Err(Error::UnsortedBreakpoints);
+        }
+        // interpolation between gains of the same sign never reaches 0
+        let sign = self.breakpoints[0].config.K_p().signum();
+        if self.breakpoints.iter().any(|breakpoint| {
+            breakpoint.config.K_p() == 0f32 || breakpoint.config.K_p().signum() != sign
+        }) {
+            return Err(Error::InvalidConfig(pid::Error::ZeroGain));
+        }
+        Ok(())
+    }
+}
+
+/// pid controller with gains scheduled over an operating point
+#[derive(PartialEq, Debug)]
+pub struct GainScheduledPID<const N: usize> {
+    /// scheduled controller
+    pid: PID,
+    /// most recently scheduled pid configuration
+    scheduled: PIDConfig,
+    /// configuration
+    config: GainScheduleConfig<N>,
+}
+
+impl<const N: usize> GainScheduledPID<N> {
+    /// create new gain scheduled pid, starting with the gains of the first breakpoint
+    pub fn new(cfg: GainScheduleConfig<N>, f_sampling_Hz: f32) -> Result<Self, Error> {
+        cfg.validate()?;
+        let scheduled = cfg.breakpoints[0].config;
+        let pid = PID::try_new(scheduled, f_sampling_Hz).map_err(Error::InvalidConfig)?;
+        Ok(GainScheduledPID {
+            pid,
+            scheduled,
+            config: cfg,
+        })
+    }
+
+    /// update controller with new controller error value at an operating point
+    pub fn update(&mut self, error: f32, operating_point: f32) -> f32 {
+        self.schedule(operating_point);
+        self.pid.update(error)
+    }
+
+    /// update two degree of freedom controller at an operating point, see [PID::update_2dof]
+    pub fn update_2dof(
+        &mut self,
+        setpoint: f32,
+        measurement: f32,
+        feedforward: f32,
+        operating_point: f32,
+    ) -> f32 {
+        self.schedule(operating_point);
+        self.pid.update_2dof(setpoint, measurement, feedforward)
+    }
+
+    /// most recently scheduled pid configuration
+    pub fn config(&self) -> PIDConfig {
+        self.scheduled
+    }
+
+    /// select anti windup strategy, see [PID::set_anti_windup]
+    pub fn set_anti_windup(&mut self, anti_windup: AntiWindup) -> Result<(), Error> {
+        self.pid
+            .set_anti_windup(anti_windup)
+            .map_err(Error::InvalidConfig)
+    }
+
+    /// select low pass on the differentiator channel, see [PID::set_derivative_filter]
+    pub fn set_derivative_filter(&mut self, filter: DerivativeFilter) -> Result<(), Error> {
+        self.pid
+            .set_derivative_filter(filter)
+            .map_err(Error::InvalidConfig)
+    }
+
+    /// select input of the differentiator channel, see [PID::set_derivative_source]
+    pub fn set_derivative_source(&mut self, source: DerivativeSource) {
+        self.pid.set_derivative_source(source);
+    }
+
+    /// set setpoint weights, see [PID::set_setpoint_weights]
+    pub fn set_setpoint_weights(&mut self, b: f32, c: f32) -> Result<(), Error> {
+        self.pid
+            .set_setpoint_weights(b, c)
+            .map_err(Error::InvalidConfig)
+    }
+
+    /// feed back the output that was actually applied, see [PID::saturation_feedback]
+    pub fn saturation_feedback(&mut self, output_applied: f32) {
+        self.pid.saturation_feedback(output_applied);
+    }
+
+    /// switch gains to the operating point without output jump
+    fn schedule(&mut self, operating_point: f32) {
+        let cfg = self.config.config_at(operating_point);
+        if cfg != self.scheduled {
+            // interpolated configs of valid breakpoints are valid as well
+            if self.pid.set_config_bumpless(cfg).is_ok() {
+                self.scheduled = cfg;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(interpolation: Interpolation) -> GainScheduleConfig<3> {
+        let breakpoint = |operating_point: f32, K_p: f32| Breakpoint {
+            operating_point,
+            config: PIDConfig::new(K_p, 10f32, 0f32, (-100f32, 100f32)).unwrap(),
+        };
+        GainScheduleConfig {
+            breakpoints: [
+                breakpoint(0f32, 1f32),
+                breakpoint(100f32, 3f32),
+                breakpoint(200f32, 4f32),
+            ],
+            interpolation,
+        }
+    }
+
+    #[test]
+    fn interpolation() {
+        let linear = config(Interpolation::Linear);
+        assert_eq!(linear.config_at(-10f32).K_p(), 1f32);
+        assert_eq!(linear.config_at(50f32).K_p(), 2f32);
+        assert_eq!(linear.config_at(150f32).K_p(), 3.5f32);
+        assert_eq!(linear.config_at(1000f32).K_p(), 4f32);
+
+        let smooth = config(Interpolation::Smooth);
+        assert_eq!(smooth.config_at(50f32).K_p(), 2f32);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            smooth.config_at(25f32).K_p(),
+            1f32 + 2f32 * 0.15625f32,
+            epsilon = 1e-5
+        ));
+        assert_eq!(smooth.config_at(100f32).K_p(), 3f32);
+    }
+
+    #[test]
+    fn invalid_table() {
+        let mut cfg = config(Interpolation::Linear);
+        cfg.breakpoints[2].operating_point = 50f32;
+        assert_eq!(
+            GainScheduledPID::new(cfg, 1000f32),
+            Err(Error::UnsortedBreakpoints)
+        );
+
+        let mut cfg = config(Interpolation::Linear);
+        cfg.breakpoints[1].config = PIDConfig::new(-1f32, 10f32, 0f32, (-100f32, 100f32)).unwrap();
+        assert_eq!(
+            GainScheduledPID::new(cfg, 1000f32),
+            Err(Error::InvalidConfig(pid::Error::ZeroGain))
+        );
+
+        let cfg = GainScheduleConfig::<0> {
+            breakpoints: [],
+            interpolation: Interpolation::Linear,
+        };
+        assert_eq!(GainScheduledPID::new(cfg, 1000f32), Err(Error::EmptyTable));
+    }
+
+    #[test]
+    fn bumpless_schedule() {
+        let mut pid = GainScheduledPID::new(config(Interpolation::Linear), 1000f32).unwrap();
+        let mut output = 0f32;
+        for _ in 0..100 {
+            output = pid.update(1f32, 0f32);
+        }
+
+        // operating point jumps, output doesn't
+        let output_new = pid.update(1f32, 200f32);
+        assert_eq!(pid.config().K_p(), 4f32);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            output_new,
+            output + 4f32 * 10f32 / 1000f32,
+            epsilon = 1e-4
+        ));
+    }
+
+    #[test]
+    fn constant_operating_point() {
+        let mut scheduled = GainScheduledPID::new(config(Interpolation::Smooth), 1000f32).unwrap();
+        let mut plain = PID::new(scheduled.config.config_at(37f32), 1000f32);
+
+        // gains are switched once, then left alone
+        scheduled.update(1f32, 37f32);
+        let cfg = scheduled.config();
+        plain.update(1f32);
+        for n in 0..1000 {
+            let error = (n as f32 * 0.01f32).sin();
+            assert_eq!(scheduled.update(error, 37f32), plain.update(error));
+        }
+        assert_eq!(scheduled.config(), cfg);
+    }
+}
@@ -27,9 +27,12 @@ use crate::motor::Mechanical;
 
 const HALL_SUM_TO_SECTOR_NO: [i8; 8] = [-10, 0, 2, 1, 4, 5, 3, -10];
 
-enum Error {
+/// hall sensor errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
     /// Sensors show impossible input like all false or all true
     ImpossibleSensorInput,
+    /// new sector is not a neighbour of the recent one
     SectorSkipped,
 }
 
@@ -40,7 +43,32 @@ pub struct Hall {
     speed_recent: f32,
 }
 
+/// look up the sector number for a set of hall sensor states
+fn hall_to_sector(hall_1: bool, hall_2: bool, hall_3: bool) -> Result<i8, Error> {
+    // calc hall number
+    let hall_no = (hall_1 as u8 + (hall_2 as u8) * 2 + (hall_3 as u8) * 4) as usize;
+    // find new sector by table
+    let sector = HALL_SUM_TO_SECTOR_NO[hall_no];
+    if sector < 0 {
+        return Err(Error::ImpossibleSensorInput);
+    }
+    Ok(sector)
+}
+
 impl Hall {
+    /// create new hall estimator from the current hall sensor states with the rotor at rest
+    pub fn new(hall_1: bool, hall_2: bool, hall_3: bool) -> Result<Hall, Error> {
+        Ok(Hall {
+            recent_sector: hall_to_sector(hall_1, hall_2, hall_3)?,
+            speed_recent: 0f32,
+        })
+    }
+
+    /// sector the rotor was found in on the most recent hall state change
+    pub fn sector(&self) -> i8 {
+        self.recent_sector
+    }
+
     /// run this method on a state change of any hall sensor, at best in a fast interrupt
     /// put in the logical state of the hall sensors and the time between now and the last
     /// interrupt to be able to calculate speed
@@ -51,13 +79,7 @@ impl Hall {
         hall_3: bool,
         t_hall_state: f32,
     ) -> Result<Mechanical, Error> {
-        // calc hall number
-        let hall_no = (hall_1 as u8 + (hall_2 as u8) * 2 + (hall_3 as u8) * 4) as usize;
-        // find new sector by table
-        let sector = HALL_SUM_TO_SECTOR_NO[hall_no];
-        if sector == -1 {
-            return Err(Error::ImpossibleSensorInput);
-        }
+        let sector = hall_to_sector(hall_1, hall_2, hall_3)?;
         // find out direction by compare previous sector
         let sector_diff = sector - self.recent_sector;
         // calc speed
@@ -98,10 +120,11 @@ impl Hall {
         // TODO: change result type to result type to make sure there's a response to sensor error
         // (like all false or all true inputs) and sector jumps (like 0 to 2)
 
-        self.speed_recent = motorstate?.speed;
+        let motorstate = motorstate?;
+        self.speed_recent = motorstate.speed;
         self.recent_sector = sector;
 
-        motorstate
+        Ok(motorstate)
     }
 }
 
@@ -119,7 +142,9 @@ mod tests {
         };
 
         // simulate new sector in clockwise direction (sector 1)
-        let res_cw_1ms = hall.interrupt_service_routine(true, true, false, 1e-3f32);
+        let res_cw_1ms = hall
+            .interrupt_service_routine(true, true, false, 1e-3f32)
+            .unwrap();
         let sol_cw_1ms = Mechanical {
             angle: core::f32::consts::PI / 6f32,
             speed: core::f32::consts::PI / 3f32 / 1e-3f32,
@@ -145,7 +170,9 @@ mod tests {
         ));
 
         // simulate new sector in clockwise direction (sector 2)
-        let res_cw_2ms = hall.interrupt_service_routine(false, true, false, 2e-3f32);
+        let res_cw_2ms = hall
+            .interrupt_service_routine(false, true, false, 2e-3f32)
+            .unwrap();
         let sol_cw_2ms = Mechanical {
             angle: core::f32::consts::PI * 3f32 / 6f32,
             speed: core::f32::consts::PI / 3f32 / 2e-3f32,
@@ -171,7 +198,9 @@ mod tests {
         ));
 
         // simulate new sector in clockwise direction (sector 3)
-        let res_cw_2ms = hall.interrupt_service_routine(false, true, true, 2e-3f32);
+        let res_cw_2ms = hall
+            .interrupt_service_routine(false, true, true, 2e-3f32)
+            .unwrap();
         let sol_cw_2ms = Mechanical {
             angle: core::f32::consts::PI * 5f32 / 6f32,
             speed: core::f32::consts::PI / 3f32 / 2e-3f32,
@@ -197,7 +226,9 @@ mod tests {
         ));
 
         // simulate new sector in clockwise direction (sector 4)
-        let res_cw_2ms = hall.interrupt_service_routine(false, false, true, 2e-3f32);
+        let res_cw_2ms = hall
+            .interrupt_service_routine(false, false, true, 2e-3f32)
+            .unwrap();
         let sol_cw_2ms = Mechanical {
             angle: core::f32::consts::PI * 7f32 / 6f32,
             speed: core::f32::consts::PI / 3f32 / 2e-3f32,
@@ -223,7 +254,9 @@ mod tests {
         ));
 
         // simulate new sector in clockwise direction (sector 5)
-        let res_cw_2ms = hall.interrupt_service_routine(true, false, true, 2e-3f32);
+        let res_cw_2ms = hall
+            .interrupt_service_routine(true, false, true, 2e-3f32)
+            .unwrap();
         let sol_cw_2ms = Mechanical {
             angle: core::f32::consts::PI * 9f32 / 6f32,
             speed: core::f32::consts::PI / 3f32 / 2e-3f32,
@@ -249,7 +282,9 @@ mod tests {
         ));
 
         // simulate new sector in clockwise direction (sector 0)
-        let res_cw_2ms = hall.interrupt_service_routine(true, false, false, 2e-3f32);
+        let res_cw_2ms = hall
+            .interrupt_service_routine(true, false, false, 2e-3f32)
+            .unwrap();
         let sol_cw_2ms = Mechanical {
             angle: core::f32::consts::PI * 11f32 / 6f32,
             speed: core::f32::consts::PI / 3f32 / 2e-3f32,
@@ -286,7 +321,9 @@ mod tests {
         };
 
         // simulate new sector in clockwise direction (sector 5)
-        let res_cw_1ms = hall.interrupt_service_routine(true, false, true, 1e-3f32);
+        let res_cw_1ms = hall
+            .interrupt_service_routine(true, false, true, 1e-3f32)
+            .unwrap();
         assert!(float_cmp::approx_eq!(
             f32,
             res_cw_1ms.angle,
@@ -294,7 +331,9 @@ mod tests {
             epsilon = 0.001
         ));
         // simulate new sector in clockwise direction (sector 4)
-        let res_cw_1ms = hall.interrupt_service_routine(false, false, true, 1e-3f32);
+        let res_cw_1ms = hall
+            .interrupt_service_routine(false, false, true, 1e-3f32)
+            .unwrap();
         assert!(float_cmp::approx_eq!(
             f32,
             res_cw_1ms.angle,
@@ -302,7 +341,9 @@ mod tests {
             epsilon = 0.001
         ));
         // simulate new sector in clockwise direction (sector 3)
-        let res_cw_1ms = hall.interrupt_service_routine(false, true, true, 1e-3f32);
+        let res_cw_1ms = hall
+            .interrupt_service_routine(false, true, true, 1e-3f32)
+            .unwrap();
         assert!(float_cmp::approx_eq!(
             f32,
             res_cw_1ms.angle,
@@ -310,7 +351,9 @@ mod tests {
             epsilon = 0.001
         ));
         // simulate new sector in clockwise direction (sector 2)
-        let res_cw_1ms = hall.interrupt_service_routine(false, true, false, 1e-3f32);
+        let res_cw_1ms = hall
+            .interrupt_service_routine(false, true, false, 1e-3f32)
+            .unwrap();
         assert!(float_cmp::approx_eq!(
             f32,
             res_cw_1ms.angle,
@@ -318,7 +361,9 @@ mod tests {
             epsilon = 0.001
         ));
         // simulate new sector in clockwise direction (sector 1)
-        let res_cw_1ms = hall.interrupt_service_routine(true, true, false, 1e-3f32);
+        let res_cw_1ms = hall
+            .interrupt_service_routine(true, true, false, 1e-3f32)
+            .unwrap();
         assert!(float_cmp::approx_eq!(
             f32,
             res_cw_1ms.angle,
@@ -326,7 +371,9 @@ mod tests {
             epsilon = 0.001
         ));
         // simulate new sector in clockwise direction (sector 0)
-        let res_cw_1ms = hall.interrupt_service_routine(true, false, false, 1e-3f32);
+        let res_cw_1ms = hall
+            .interrupt_service_routine(true, false, false, 1e-3f32)
+            .unwrap();
         assert!(float_cmp::approx_eq!(
             f32,
             res_cw_1ms.angle,
@@ -334,7 +381,9 @@ mod tests {
             epsilon = 0.001
         ));
         // simulate new sector in clockwise direction (sector 5)
-        let res_cw_1ms = hall.interrupt_service_routine(true, false, true, 1e-3f32);
+        let res_cw_1ms = hall
+            .interrupt_service_routine(true, false, true, 1e-3f32)
+            .unwrap();
         assert!(float_cmp::approx_eq!(
             f32,
             res_cw_1ms.angle,
@@ -0,0 +1,150 @@
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+#![allow(non_snake_case)]
+
+//! lead-lag compensator
+//!
+//! K (1 + T_lead s) / (1 + T_lag s) lifts or lowers the phase between 1/T_lead and 1/T_lag. With
+//! T_lead above T_lag it's a lead, which buys phase margin around the crossover of an outer loop.
+//! With T_lead below T_lag it's a lag, which adds gain at low frequencies without touching the
+//! crossover.
+//!
+//! It's discretized with the tustin transformation.
+
+/// lead-lag errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// a time constant is not positive and finite
+    InvalidTimeConstant,
+    /// sampling frequency is not positive and finite
+    InvalidSampleTime,
+}
+
+/// lead-lag state
+pub struct LeadLag {
+    /// most recent input
+    input_recent: f32,
+    /// most recent output
+    output: f32,
+    /// input coefficient
+    b0: f32,
+    /// recent input coefficient
+    b1: f32,
+    /// recent output coefficient
+    a1: f32,
+    /// configuration
+    config: LeadLagConfig,
+}
+
+/// configuration structure for lead-lag
+pub struct LeadLagConfig {
+    /// amplification at low frequencies
+    pub K_p: f32,
+    /// time constant of the zero
+    pub T_lead: f32,
+    /// time constant of the pole
+    pub T_lag: f32,
+}
+
+impl LeadLag {
+    /// create new lead-lag
+    pub fn new(cfg: LeadLagConfig, f_sample_Hz: f32) -> Result<LeadLag, Error> {
+        if [cfg.T_lead, cfg.T_lag]
+            .iter()
+            .any(|T| !T.is_finite() || *T <= 0f32)
+        {
+            return Err(Error::InvalidTimeConstant);
+        }
+        if !f_sample_Hz.is_finite() || f_sample_Hz <= 0f32 {
+            return Err(Error::InvalidSampleTime);
+        }
+
+        // s = c (1 - z⁻¹) / (1 + z⁻¹)
+        let c = 2f32 * f_sample_Hz;
+        let a0 = 1f32 + c * cfg.T_lag;
+
+        Ok(LeadLag {
+            input_recent: 0f32,
+            output: 0f32,
+            b0: cfg.K_p * (1f32 + c * cfg.T_lead) / a0,
+            b1: cfg.K_p * (1f32 - c * cfg.T_lead) / a0,
+            a1: (1f32 - c * cfg.T_lag) / a0,
+            config: cfg,
+        })
+    }
+
+    /// update compensator with new data
+    pub fn update(&mut self, input: f32) -> f32 {
+        self.output = self.b0 * input + self.b1 * self.input_recent - self.a1 * self.output;
+        self.input_recent = input;
+
+        self.output
+    }
+
+    /// reset compensator to given steady state output. if in doubt, use 0
+    pub fn reset(&mut self, reset_value: f32) {
+        self.output = reset_value;
+        self.input_recent = if self.config.K_p != 0f32 {
+            reset_value / self.config.K_p
+        } else {
+            0f32
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lead() {
+        let cfg = LeadLagConfig {
+            K_p: 2f32,
+            T_lead: 0.01f32,
+            T_lag: 0.001f32,
+        };
+        let mut lead = LeadLag::new(cfg, 10000f32).unwrap();
+
+        // step goes through with the high frequency gain first
+        let first = lead.update(1f32);
+        assert!(first > 10f32);
+        // and settles on the low frequency gain
+        for _ in 0..1000 {
+            lead.update(1f32);
+        }
+        assert!(float_cmp::approx_eq!(
+            f32,
+            lead.output,
+            2f32,
+            epsilon = 1e-4
+        ));
+
+        // reset to steady state
+        lead.reset(-4f32);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            lead.update(-2f32),
+            -4f32,
+            epsilon = 1e-5
+        ));
+    }
+
+    #[test]
+    fn invalid_config() {
+        let cfg = |T_lead: f32, T_lag: f32| LeadLagConfig {
+            K_p: 1f32,
+            T_lead,
+            T_lag,
+        };
+        for (T_lead, T_lag) in [(0f32, 0.001f32), (0.01f32, -0.001f32), (f32::NAN, 0.001f32)] {
+            assert_eq!(
+                LeadLag::new(cfg(T_lead, T_lag), 10000f32).err(),
+                Some(Error::InvalidTimeConstant)
+            );
+        }
+        assert_eq!(
+            LeadLag::new(cfg(0.01f32, 0.001f32), -1f32).err(),
+            Some(Error::InvalidSampleTime)
+        );
+    }
+}
@@ -4,9 +4,26 @@
 
 //! rust library for generic motor control of pmdc motors
 
+pub mod absolute_encoder;
+pub mod adaptive_notch;
+pub mod autotune;
+pub mod bemf;
+pub mod biquad;
+pub mod calibration;
+pub mod current_controller;
 pub mod dq;
+pub mod encoder;
+pub mod fusion;
+pub mod gain_schedule;
 pub mod hall;
+pub mod lead_lag;
+pub mod motion;
 pub mod motor;
 pub mod pid;
 pub mod pll;
 pub mod pt1;
+pub mod pt2;
+pub mod ramp;
+pub mod resolver;
+pub mod six_step;
+pub mod tuning;
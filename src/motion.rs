@@ -0,0 +1,607 @@
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+#![allow(non_snake_case)]
+
+//! point to point motion profiles
+//!
+//! A [ramp](crate::ramp) shapes a speed setpoint, but doesn't know where it's going. For a move to
+//! a position, the profile has to know when to brake, and the fastest move within the limits is
+//! known in advance. So [Profile] plans the whole move on construction, and streams it afterwards.
+//!
+//! A move changes speed from the start to a peak speed, cruises, and changes speed down to
+//! standstill at the target. With the [trapezoidal](Shape::Trapezoidal) shape, speed changes with
+//! full acceleration, which gives three segments. The [S-curve](Shape::SCurve) limits jerk as well:
+//! each speed change has acceleration ramping up, staying and ramping down, which makes seven
+//! segments.
+//!
+//! A speed change from v_a to v_b takes
+//!
+//! T = |v_b - v_a| / a + a / j
+//!
+//! if it reaches full acceleration, and 2 √(|v_b - v_a| / j) if not. Either way it covers
+//! (v_a + v_b) / 2 T, since acceleration is symmetric. Short moves don't have room to reach the
+//! speed limit, so the peak speed is searched for such that both speed changes make up the
+//! distance, and there's no cruise at all.
+//!
+//! The move starts from the speed of the given state, but the acceleration is taken as zero, like
+//! it is between moves. If the axis moves away from the target or is too fast to stop in time, the
+//! profile brakes to standstill first and moves back from there.
+//!
+//! Positions span many turns, so the angle of the samples isn't wrapped.
+//!
+//! Axes that have to move together, like the two of an XY gantry, each get their own profile, and
+//! they'd all arrive at different times. [Coordinator] stretches the faster ones to the duration
+//! of the slowest. Sampling a profile at t k instead of t, with k being its own duration over the
+//! common one, makes it last longer. Speed scales with k and acceleration with k², so everything
+//! stays within the limits it was planned with.
+
+use crate::motor::Mechanical;
+#[cfg_attr(test, allow(unused_imports))]
+use num_traits::Float;
+
+/// most segments: braking, speeding up, cruising and slowing down
+const SEGMENTS_MAX: usize = 10;
+
+/// bisection steps for the peak speed of short moves
+const BISECTION_STEPS: usize = 40;
+
+/// motion profile errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// a limit or the sampling frequency is not positive and finite
+    InvalidLimit,
+    /// start or target is not finite
+    InvalidPosition,
+    /// an axis that needs to be slowed down doesn't start from rest
+    MovingStart,
+    /// profiles were planned with different sampling frequencies
+    SampleRateMismatch,
+}
+
+/// shape of the speed changes
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Shape {
+    /// constant acceleration, steps in acceleration
+    Trapezoidal,
+    /// jerk limited, steps in jerk only
+    SCurve,
+}
+
+/// configuration struct for motion profile construction
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct MotionConfig {
+    /// shape of the speed changes
+    pub shape: Shape,
+    /// highest speed magnitude in rad per second
+    pub speed_max: f32,
+    /// highest acceleration magnitude in rad per second²
+    pub acceleration_max: f32,
+    /// highest jerk magnitude in rad per second³, for S-curves only
+    pub jerk_max: f32,
+}
+
+impl MotionConfig {
+    /// time it takes to change speed by dv
+    fn change_time(&self, dv: f32) -> f32 {
+        let dv = dv.abs();
+        let a = self.acceleration_max;
+        match self.shape {
+            Shape::Trapezoidal => dv / a,
+            Shape::SCurve => {
+                let j = self.jerk_max;
+                if dv >= a * a / j {
+                    dv / a + a / j
+                } else {
+                    2f32 * (dv / j).sqrt()
+                }
+            }
+        }
+    }
+
+    /// distance covered while changing speed from v_a to v_b
+    fn change_distance(&self, v_a: f32, v_b: f32) -> f32 {
+        (v_a + v_b) / 2f32 * self.change_time(v_b - v_a)
+    }
+
+    /// check limits for positive finite values
+    fn validate(&self) -> Result<(), Error> {
+        let mut limits = [self.speed_max, self.acceleration_max, self.jerk_max].into_iter();
+        let valid = |value: f32| value.is_finite() && value > 0f32;
+        let valid = match self.shape {
+            Shape::Trapezoidal => limits.take(2).all(valid),
+            Shape::SCurve => limits.all(valid),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::InvalidLimit)
+        }
+    }
+}
+
+/// segment of constant jerk and the state it starts from
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+struct Segment {
+    /// time since start of the move
+    t_start: f32,
+    /// duration
+    duration: f32,
+    /// angle at start
+    angle: f32,
+    /// speed at start
+    speed: f32,
+    /// acceleration at start
+    acceleration: f32,
+    /// jerk
+    jerk: f32,
+}
+
+impl Segment {
+    /// state at time t into the segment
+    fn state(&self, t: f32) -> Mechanical {
+        Mechanical {
+            angle: self.angle
+                + self.speed * t
+                + self.acceleration * t * t / 2f32
+                + self.jerk * t * t * t / 6f32,
+            speed: self.speed + self.acceleration * t + self.jerk * t * t / 2f32,
+            acceleration: self.acceleration + self.jerk * t,
+        }
+    }
+}
+
+/// planned point to point move
+#[derive(PartialEq, Debug)]
+pub struct Profile {
+    /// planned segments
+    segments: [Segment; SEGMENTS_MAX],
+    /// number of planned segments
+    count: usize,
+    /// target angle
+    target: f32,
+    /// total move time
+    duration: f32,
+    /// time since start of the move
+    t: f32,
+    /// sample time
+    t_sample: f32,
+}
+
+impl Profile {
+    /// plan the fastest move within the limits from the start state to rest at the target angle
+    pub fn new(
+        cfg: &MotionConfig,
+        start: &Mechanical,
+        target: f32,
+        f_sample_Hz: f32,
+    ) -> Result<Profile, Error> {
+        cfg.validate()?;
+        if !(f_sample_Hz.is_finite() && f_sample_Hz > 0f32) {
+            return Err(Error::InvalidLimit);
+        }
+        if !(start.angle.is_finite() && start.speed.is_finite() && target.is_finite()) {
+            return Err(Error::InvalidPosition);
+        }
+
+        let mut profile = Profile {
+            segments: [Segment::default(); SEGMENTS_MAX],
+            count: 0,
+            target,
+            duration: 0f32,
+            t: 0f32,
+            t_sample: 1f32 / f_sample_Hz,
+        };
+        let mut state = Mechanical {
+            angle: start.angle,
+            speed: start.speed,
+            acceleration: 0f32,
+        };
+
+        // in direction of the target
+        let direction = if target >= start.angle { 1f32 } else { -1f32 };
+        let distance = (target - start.angle).abs();
+        let speed = direction * start.speed;
+        let speed_low = speed.min(cfg.speed_max);
+        let overshoot = speed < 0f32
+            || cfg.change_distance(speed, speed_low) + cfg.change_distance(speed_low, 0f32)
+                > distance;
+
+        if overshoot {
+            // stop first, then start over from rest
+            profile.push_change(cfg, &mut state, 0f32);
+            let distance = target - state.angle;
+            let direction = if distance >= 0f32 { 1f32 } else { -1f32 };
+            profile.push_move(cfg, &mut state, direction, 0f32, distance.abs());
+        } else {
+            profile.push_move(cfg, &mut state, direction, speed, distance);
+        }
+        Ok(profile)
+    }
+
+    /// total move time in seconds
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    /// time since start of the move in seconds
+    pub fn elapsed(&self) -> f32 {
+        self.t
+    }
+
+    /// move is complete
+    pub fn done(&self) -> bool {
+        self.t >= self.duration
+    }
+
+    /// reference at time t since start of the move
+    pub fn sample(&self, t: f32) -> Mechanical {
+        match self.segments[..self.count]
+            .iter()
+            .find(|segment| t < segment.t_start + segment.duration)
+        {
+            Some(segment) => segment.state((t - segment.t_start).max(0f32)),
+            None => Mechanical {
+                angle: self.target,
+                speed: 0f32,
+                acceleration: 0f32,
+            },
+        }
+    }
+
+    /// run this method on every sample. Returns the reference for the position loop.
+    pub fn update(&mut self) -> Mechanical {
+        self.t = (self.t + self.t_sample).min(self.duration);
+        self.sample(self.t)
+    }
+
+    /// add speed up, cruise and slow down for a distance in direction of the target, starting at
+    /// a speed that can stop in time
+    fn push_move(
+        &mut self,
+        cfg: &MotionConfig,
+        state: &mut Mechanical,
+        direction: f32,
+        speed: f32,
+        distance: f32,
+    ) {
+        let travel = |peak: f32| cfg.change_distance(speed, peak) + cfg.change_distance(peak, 0f32);
+
+        let mut peak = cfg.speed_max;
+        if travel(peak) > distance {
+            // short move, find peak speed that covers the distance without cruising
+            let mut low = speed.min(cfg.speed_max);
+            for _ in 0..BISECTION_STEPS {
+                let mid = (low + peak) / 2f32;
+                if travel(mid) > distance {
+                    peak = mid;
+                } else {
+                    low = mid;
+                }
+            }
+            peak = low;
+        }
+
+        self.push_change(cfg, state, direction * peak);
+        if peak > 0f32 {
+            let cruise = (distance - travel(peak)).max(0f32) / peak;
+            self.push(state, cruise, 0f32, 0f32);
+        }
+        self.push_change(cfg, state, 0f32);
+    }
+
+    /// add segments changing speed to v_b
+    fn push_change(&mut self, cfg: &MotionConfig, state: &mut Mechanical, v_b: f32) {
+        let dv = v_b - state.speed;
+        let sign = dv.signum();
+        let a = cfg.acceleration_max;
+        match cfg.shape {
+            Shape::Trapezoidal => self.push(state, dv.abs() / a, sign * a, 0f32),
+            Shape::SCurve => {
+                let j = cfg.jerk_max;
+                let a_peak = (dv.abs() * j).sqrt().min(a);
+                let t_jerk = a_peak / j;
+                let t_constant = (dv.abs() / a_peak - t_jerk).max(0f32);
+                self.push(state, t_jerk, 0f32, sign * j);
+                self.push(state, t_constant, sign * a_peak, 0f32);
+                self.push(state, t_jerk, sign * a_peak, -sign * j);
+            }
+        }
+    }
+
+    /// add a segment and move the state to its end
+    fn push(&mut self, state: &mut Mechanical, duration: f32, acceleration: f32, jerk: f32) {
+        if duration <= 0f32 || self.count >= SEGMENTS_MAX {
+            return;
+        }
+        let segment = Segment {
+            t_start: self.duration,
+            duration,
+            angle: state.angle,
+            speed: state.speed,
+            acceleration,
+            jerk,
+        };
+        self.segments[self.count] = segment;
+        self.count += 1;
+        self.duration += duration;
+        *state = segment.state(duration);
+    }
+}
+
+/// moves of several axes, starting and ending together
+#[derive(PartialEq, Debug)]
+pub struct Coordinator<const N: usize> {
+    /// profile of each axis
+    profiles: [Profile; N],
+    /// time scale of each axis, own duration over the common one
+    scales: [f32; N],
+    /// common move time
+    duration: f32,
+    /// time since start of the move
+    t: f32,
+    /// sample time
+    t_sample: f32,
+}
+
+impl<const N: usize> Coordinator<N> {
+    /// create new coordinator from the profile of each axis, planned at the same sampling
+    /// frequency. Axes that are faster than the slowest one have to start from rest.
+    pub fn new(profiles: [Profile; N]) -> Result<Coordinator<N>, Error> {
+        let t_sample = profiles.first().map_or(0f32, |profile| profile.t_sample);
+        if profiles.iter().any(|profile| profile.t_sample != t_sample) {
+            return Err(Error::SampleRateMismatch);
+        }
+
+        let duration = profiles
+            .iter()
+            .fold(0f32, |duration, profile| duration.max(profile.duration));
+        let scales = profiles.each_ref().map(|profile| {
+            if duration > 0f32 {
+                profile.duration / duration
+            } else {
+                1f32
+            }
+        });
+        // scaling would change the start speed
+        let moving = profiles
+            .iter()
+            .zip(scales)
+            .any(|(profile, scale)| scale < 1f32 && profile.sample(0f32).speed != 0f32);
+        if moving {
+            return Err(Error::MovingStart);
+        }
+
+        Ok(Coordinator {
+            profiles,
+            scales,
+            duration,
+            t: 0f32,
+            t_sample,
+        })
+    }
+
+    /// common move time in seconds
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    /// time since start of the move in seconds
+    pub fn elapsed(&self) -> f32 {
+        self.t
+    }
+
+    /// move is complete on all axes
+    pub fn done(&self) -> bool {
+        self.t >= self.duration
+    }
+
+    /// references of all axes at time t since start of the move
+    pub fn sample(&self, t: f32) -> [Mechanical; N] {
+        core::array::from_fn(|axis| {
+            let k = self.scales[axis];
+            let state = self.profiles[axis].sample(t * k);
+            Mechanical {
+                angle: state.angle,
+                speed: state.speed * k,
+                acceleration: state.acceleration * k * k,
+            }
+        })
+    }
+
+    /// run this method on every sample. Returns the references for the position loops.
+    pub fn update(&mut self) -> [Mechanical; N] {
+        self.t = (self.t + self.t_sample).min(self.duration);
+        self.sample(self.t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const F_SAMPLE: f32 = 1000f32;
+
+    fn config(shape: Shape) -> MotionConfig {
+        MotionConfig {
+            shape,
+            speed_max: 10f32,
+            acceleration_max: 100f32,
+            jerk_max: 2000f32,
+        }
+    }
+
+    fn rest(angle: f32) -> Mechanical {
+        Mechanical {
+            angle,
+            speed: 0f32,
+            acceleration: 0f32,
+        }
+    }
+
+    /// stream the whole move, checking limits and continuity. Returns the highest speed.
+    fn run(cfg: &MotionConfig, profile: &mut Profile) -> f32 {
+        let mut recent = profile.sample(0f32);
+        let mut speed_max = 0f32;
+        while !profile.done() {
+            let state = profile.update();
+            assert!(state.speed.abs() <= cfg.speed_max * 1.001f32);
+            assert!(state.acceleration.abs() <= cfg.acceleration_max * 1.001f32);
+            assert!(
+                (state.speed - recent.speed).abs() <= cfg.acceleration_max / F_SAMPLE * 1.01f32
+            );
+            if cfg.shape == Shape::SCurve {
+                let jerk_step = cfg.jerk_max / F_SAMPLE;
+                assert!((state.acceleration - recent.acceleration).abs() <= jerk_step * 1.01f32);
+            }
+            speed_max = speed_max.max(state.speed.abs());
+            recent = state;
+        }
+        assert_eq!(recent, rest(profile.target));
+        speed_max
+    }
+
+    #[test]
+    fn trapezoidal() {
+        let cfg = config(Shape::Trapezoidal);
+
+        // long move: 0.1 s speeding up, 0.9 s cruising, 0.1 s slowing down
+        let mut profile = Profile::new(&cfg, &rest(1f32), 11f32, F_SAMPLE).unwrap();
+        assert!(float_cmp::approx_eq!(
+            f32,
+            profile.duration(),
+            1.1f32,
+            epsilon = 1e-5
+        ));
+        assert_eq!(profile.sample(0.5f32).speed, 10f32);
+        assert_eq!(run(&cfg, &mut profile), 10f32);
+
+        // short move: triangle reaching √(d a)
+        let mut profile = Profile::new(&cfg, &rest(0f32), -0.25f32, F_SAMPLE).unwrap();
+        assert!(float_cmp::approx_eq!(
+            f32,
+            profile.duration(),
+            0.1f32,
+            epsilon = 1e-5
+        ));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            run(&cfg, &mut profile),
+            5f32,
+            epsilon = 0.01
+        ));
+    }
+
+    #[test]
+    fn s_curve() {
+        let cfg = config(Shape::SCurve);
+
+        // long move: each speed change takes v / a + a / j = 0.15 s
+        let mut profile = Profile::new(&cfg, &rest(0f32), 20f32, F_SAMPLE).unwrap();
+        assert!(float_cmp::approx_eq!(
+            f32,
+            profile.duration(),
+            2.15f32,
+            epsilon = 1e-4
+        ));
+        run(&cfg, &mut profile);
+
+        // short move never reaches cruise speed, nor full acceleration
+        let mut profile = Profile::new(&cfg, &rest(0f32), 0.1f32, F_SAMPLE).unwrap();
+        assert!(run(&cfg, &mut profile) < 10f32);
+        let peak = (0..100)
+            .map(|n| profile.sample(n as f32 / 1000f32).acceleration.abs())
+            .fold(0f32, f32::max);
+        assert!(peak < 100f32);
+
+        assert_eq!(
+            Profile::new(
+                &MotionConfig {
+                    jerk_max: 0f32,
+                    ..cfg
+                },
+                &rest(0f32),
+                1f32,
+                F_SAMPLE
+            ),
+            Err(Error::InvalidLimit)
+        );
+        assert_eq!(
+            Profile::new(&cfg, &rest(0f32), f32::NAN, F_SAMPLE),
+            Err(Error::InvalidPosition)
+        );
+    }
+
+    #[test]
+    fn moving_start() {
+        let cfg = config(Shape::SCurve);
+        let moving = |speed: f32| Mechanical {
+            angle: 0f32,
+            speed,
+            acceleration: 0f32,
+        };
+
+        // already on the way: faster than from rest
+        let from_rest = Profile::new(&cfg, &rest(0f32), 5f32, F_SAMPLE).unwrap();
+        let mut profile = Profile::new(&cfg, &moving(5f32), 5f32, F_SAMPLE).unwrap();
+        assert!(profile.duration() < from_rest.duration());
+        assert_eq!(profile.sample(0f32).speed, 5f32);
+        run(&cfg, &mut profile);
+
+        // moving away: brake and come back
+        let mut profile = Profile::new(&cfg, &moving(-10f32), 1f32, F_SAMPLE).unwrap();
+        assert!(profile.sample(0.1f32).angle < 0f32);
+        run(&cfg, &mut profile);
+
+        // too fast to stop in time: overshoot and come back
+        let mut profile = Profile::new(&cfg, &moving(10f32), 0.1f32, F_SAMPLE).unwrap();
+        assert!(profile.sample(0.15f32).angle > 0.1f32);
+        run(&cfg, &mut profile);
+    }
+
+    #[test]
+    fn coordinator() {
+        let cfg = config(Shape::SCurve);
+        let x = Profile::new(&cfg, &rest(0f32), 20f32, F_SAMPLE).unwrap();
+        let y = Profile::new(&cfg, &rest(1f32), -4f32, F_SAMPLE).unwrap();
+        let z = Profile::new(&cfg, &rest(3f32), 3f32, F_SAMPLE).unwrap();
+        let duration = x.duration();
+        let y_duration = y.duration();
+        let mut gantry = Coordinator::new([x, y, z]).unwrap();
+        assert_eq!(gantry.duration(), duration);
+
+        // y is slower than planned and arrives together with x
+        let mut speed_max = 0f32;
+        let mut recent = gantry.sample(0f32);
+        while !gantry.done() {
+            let states = gantry.update();
+            if !gantry.done() {
+                assert!(states[1].angle > -4f32);
+            }
+            speed_max = speed_max.max(states[1].speed.abs());
+            assert!((states[1].speed - recent[1].speed).abs() <= cfg.acceleration_max / F_SAMPLE);
+            recent = states;
+        }
+        assert_eq!(recent, [rest(20f32), rest(-4f32), rest(3f32)]);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            speed_max,
+            // cruising at 10 rad/s with the own profile
+            10f32 * y_duration / duration,
+            epsilon = 0.01
+        ));
+
+        // a moving axis can't be stretched
+        let moving = Mechanical {
+            angle: 0f32,
+            speed: 1f32,
+            acceleration: 0f32,
+        };
+        let x = Profile::new(&cfg, &rest(0f32), 20f32, F_SAMPLE).unwrap();
+        let y = Profile::new(&cfg, &moving, 1f32, F_SAMPLE).unwrap();
+        assert_eq!(Coordinator::new([x, y]), Err(Error::MovingStart));
+
+        let x = Profile::new(&cfg, &rest(0f32), 20f32, F_SAMPLE).unwrap();
+        let y = Profile::new(&cfg, &rest(0f32), 1f32, 2f32 * F_SAMPLE).unwrap();
+        assert_eq!(Coordinator::new([x, y]), Err(Error::SampleRateMismatch));
+    }
+}
@@ -73,6 +73,27 @@ impl Mechanical {
     }
 }
 
+/// wrap angle in rad to 0 to 2π
+pub fn wrap_angle(angle: f32) -> f32 {
+    let angle = angle % (2f32 * core::f32::consts::PI);
+    if angle < 0f32 {
+        // tiny negative angles would round up to 2π
+        (angle + 2f32 * core::f32::consts::PI) % (2f32 * core::f32::consts::PI)
+    } else {
+        angle
+    }
+}
+
+/// wrap angle difference in rad to -π to π
+pub fn wrap_angle_diff(angle: f32) -> f32 {
+    let angle = wrap_angle(angle);
+    if angle > core::f32::consts::PI {
+        angle - 2f32 * core::f32::consts::PI
+    } else {
+        angle
+    }
+}
+
 /// combined motor state
 #[derive(PartialEq, Debug)]
 pub struct Motor {
@@ -4,6 +4,8 @@
 
 //! rust library for generic motor control of pmdc motors
 
+use core::f32::consts::PI;
+
 /// pid controller data struct. contains all the state of the pid
 #[derive(PartialEq, Debug)]
 pub struct PID {
@@ -11,80 +13,531 @@ pub struct PID {
     i_chan: f32,
     /// state of differentiator channel (last updated value)
     d_chan: f32,
-    /// stored most recent output of controller
+    /// most recent filtered differentiator output
+    d_out: f32,
+    /// differentiator filter
+    derivative_filter: DerivativeFilter,
+    /// differentiator input
+    derivative_source: DerivativeSource,
+    /// setpoint weight of the proportional channel
+    b: f32,
+    /// setpoint weight of the differentiator channel
+    c: f32,
+    /// most recent proportional channel input
+    p_recent: f32,
+    /// most recent feedforward
+    feedforward_recent: f32,
+    /// stored most recent output of controller, or the applied output if fed back
     output: f32,
+    /// most recent output of controller before limiting
+    output_unlimited: f32,
+    /// anti windup strategy
+    anti_windup: AntiWindup,
+    /// sampling frequency the config was scaled with
+    f_sampling: f32,
     /// pid configuration like amplifications
     config: PIDConfig,
 }
 
-/// configuration struct for pid contstruction
+/// pid configuration errors
 #[derive(PartialEq, Debug)]
+pub enum Error {
+    /// a gain or limit is infinite or NaN
+    NonFinite,
+    /// low output limit is not below high output limit
+    InvalidLimits,
+    /// sampling frequency is not a positive finite number
+    InvalidSampleRate,
+    /// back calculation tracking gain is negative or not finite
+    InvalidTrackingGain,
+    /// derivative filter coefficient or cutoff is not a positive finite number
+    InvalidDerivativeFilter,
+    /// bumpless gain change to a K_p of 0, which can't hold the output
+    ZeroGain,
+}
+
+/// anti windup strategy of the integrator
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum AntiWindup {
+    /// clamp the integrator to the output limits
+    Clamping,
+    /// stop integrating while the output is saturated and the error drives it further into
+    /// saturation
+    ConditionalIntegration,
+    /// feed the difference of limited and unlimited output back into the integrator
+    BackCalculation {
+        /// tracking gain in 1/s. The integrator follows the limited output with this rate.
+        K_t: f32,
+    },
+}
+
+/// first order low pass on the differentiator channel
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum DerivativeFilter {
+    /// raw difference of the input
+    None,
+    /// filter time constant K_d / N. N of 2 to 20 is usual, 20 being the lightest filter.
+    N(f32),
+    /// cutoff frequency in Hz
+    Cutoff(f32),
+}
+
+impl DerivativeFilter {
+    /// check for positive finite filter parameters
+    fn validate(&self) -> Result<(), Error> {
+        match self {
+            DerivativeFilter::N(value) | DerivativeFilter::Cutoff(value)
+                if !value.is_finite() || *value <= 0f32 =>
+            {
+                Err(Error::InvalidDerivativeFilter)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// filter time constant T_f in seconds for differentiator gain K_d
+    fn time_constant(&self, K_d: f32) -> f32 {
+        match self {
+            DerivativeFilter::None => 0f32,
+            DerivativeFilter::N(N) => K_d / N,
+            DerivativeFilter::Cutoff(f_cutoff) => 1f32 / (2f32 * PI * f_cutoff),
+        }
+    }
+}
+
+/// input of the differentiator channel
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum DerivativeSource {
+    /// control error, kicks on every setpoint step
+    Error,
+    /// negative measurement, ignores the setpoint. Same as a setpoint weight c of 0. Needs
+    /// [PID::update_setpoint] or [PID::update_2dof].
+    Measurement,
+}
+
+/// configuration struct for pid contstruction. Build it with [PIDConfig::new], so it's always
+/// validated.
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub struct PIDConfig {
     /// P amplification of input
     K_p: f32,
     /// Integrator amplification, a.k.a. T_n
     K_i: f32,
-    /// differentiator amplification, a.k.a. T_v
+    /// differentiator amplification in seconds, a.k.a. T_v. The D channel adds K_d times the
+    /// change of its input per second, so K_d doesn't depend on the sampling frequency.
     K_d: f32,
-    /// high output limit
-    limit_high: f32,
     /// low output limit
     limit_low: f32,
+    /// high output limit
+    limit_high: f32,
+}
+
+impl PIDConfig {
+    /// create new validated pid configuration with output limits as (low, high)
+    pub fn new(K_p: f32, K_i: f32, K_d: f32, limits: (f32, f32)) -> Result<PIDConfig, Error> {
+        let (limit_low, limit_high) = limits;
+        let cfg = PIDConfig {
+            K_p,
+            K_i,
+            K_d,
+            limit_low,
+            limit_high,
+        };
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    /// P amplification of input
+    pub fn K_p(&self) -> f32 {
+        self.K_p
+    }
+
+    /// Integrator amplification, a.k.a. T_n
+    pub fn K_i(&self) -> f32 {
+        self.K_i
+    }
+
+    /// differentiator amplification in seconds, a.k.a. T_v
+    pub fn K_d(&self) -> f32 {
+        self.K_d
+    }
+
+    /// output limits as (low, high)
+    pub fn limits(&self) -> (f32, f32) {
+        (self.limit_low, self.limit_high)
+    }
+
+    /// check for finite values and limits in the right order
+    fn validate(&self) -> Result<(), Error> {
+        let values = [
+            self.K_p,
+            self.K_i,
+            self.K_d,
+            self.limit_low,
+            self.limit_high,
+        ];
+        if values.iter().any(|value| !value.is_finite()) {
+            return Err(Error::NonFinite);
+        }
+        if self.limit_low >= self.limit_high {
+            return Err(Error::InvalidLimits);
+        }
+        Ok(())
+    }
 }
 
 impl PID {
     /// create new pid controller from config
     pub fn new(cfg: PIDConfig, f_sampling_Hz: f32) -> PID {
-        let newconfig = PIDConfig {
-            K_i: cfg.K_i / f_sampling_Hz,
-            K_d: cfg.K_d / f_sampling_Hz,
-            ..cfg
-        };
-
         PID {
             i_chan: 0f32,
             d_chan: 0f32,
+            d_out: 0f32,
+            derivative_filter: DerivativeFilter::None,
+            derivative_source: DerivativeSource::Error,
+            b: 1f32,
+            c: 1f32,
+            p_recent: 0f32,
+            feedforward_recent: 0f32,
             output: 0f32,
-            config: newconfig,
+            output_unlimited: 0f32,
+            anti_windup: AntiWindup::Clamping,
+            f_sampling: f_sampling_Hz,
+            config: scale_config(cfg, f_sampling_Hz),
+        }
+    }
+
+    /// create new pid controller from config after validating config and sampling frequency
+    pub fn try_new(cfg: PIDConfig, f_sampling_Hz: f32) -> Result<PID, Error> {
+        cfg.validate()?;
+        if !f_sampling_Hz.is_finite() || f_sampling_Hz <= 0f32 {
+            return Err(Error::InvalidSampleRate);
+        }
+        Ok(PID::new(cfg, f_sampling_Hz))
+    }
+
+    /// configuration as it was given on construction
+    pub fn config(&self) -> PIDConfig {
+        PIDConfig {
+            K_i: self.config.K_i * self.f_sampling,
+            ..self.config
         }
     }
 
-    /// update controller with new controller error value
+    /// replace configuration at runtime. The controller states are kept.
+    pub fn set_config(&mut self, cfg: PIDConfig) -> Result<(), Error> {
+        cfg.validate()?;
+        self.config = scale_config(cfg, self.f_sampling);
+        Ok(())
+    }
+
+    /// replace gains at runtime. Limits and controller states are kept.
+    pub fn set_gains(&mut self, K_p: f32, K_i: f32, K_d: f32) -> Result<(), Error> {
+        self.set_config(PIDConfig {
+            K_p,
+            K_i,
+            K_d,
+            ..self.config()
+        })
+    }
+
+    /// replace configuration at runtime without a jump in the output. The integrator is
+    /// recalculated so that the most recent output stays the same with the new gains. K_p scales
+    /// all channels, so a K_p of 0 is refused.
+    pub fn set_config_bumpless(&mut self, cfg: PIDConfig) -> Result<(), Error> {
+        cfg.validate()?;
+        if cfg.K_p == 0f32 {
+            return Err(Error::ZeroGain);
+        }
+        if self.config.K_d != 0f32 {
+            self.d_out *= cfg.K_d / self.config.K_d;
+        } else {
+            self.d_out = 0f32;
+        }
+        self.config = scale_config(cfg, self.f_sampling);
+        self.back_calculate(self.output_unlimited);
+        Ok(())
+    }
+
+    /// replace gains at runtime without a jump in the output. Limits are kept.
+    pub fn set_gains_bumpless(&mut self, K_p: f32, K_i: f32, K_d: f32) -> Result<(), Error> {
+        self.set_config_bumpless(PIDConfig {
+            K_p,
+            K_i,
+            K_d,
+            ..self.config()
+        })
+    }
+
+    /// follow an output given by someone else, like a manual mode or an open loop startup, with
+    /// the current error. Call it on every sample instead of [PID::update] until you hand over
+    /// control. The controller states are calculated so that it continues exactly at this output.
+    pub fn track(&mut self, output: f32, error: f32) {
+        // same inputs as update, regardless of setpoint weights and derivative source
+        self.derivative(error);
+        self.p_recent = error;
+        self.feedforward_recent = 0f32;
+        self.back_calculate(output);
+        self.output = output;
+    }
+
+    /// follow an output given by someone else like [PID::track], with setpoint, measurement and
+    /// feedforward as for [PID::update_2dof]
+    pub fn track_2dof(&mut self, output: f32, setpoint: f32, measurement: f32, feedforward: f32) {
+        let c = match self.derivative_source {
+            DerivativeSource::Error => self.c,
+            DerivativeSource::Measurement => 0f32,
+        };
+        self.derivative(c * setpoint - measurement);
+        self.p_recent = self.b * setpoint - measurement;
+        self.feedforward_recent = feedforward;
+        self.back_calculate(output);
+        self.output = output;
+    }
+
+    /// select anti windup strategy. Clamping is used by default.
+    pub fn set_anti_windup(&mut self, anti_windup: AntiWindup) -> Result<(), Error> {
+        if let AntiWindup::BackCalculation { K_t } = anti_windup {
+            if !K_t.is_finite() || K_t < 0f32 {
+                return Err(Error::InvalidTrackingGain);
+            }
+        }
+        self.anti_windup = anti_windup;
+        Ok(())
+    }
+
+    /// feed back the output that was actually applied, if stages after the controller like voltage
+    /// limitation or modulation clipped it further. Call it after [PID::update], before the next
+    /// one. Conditional integration and back calculation take it into account.
+    pub fn saturation_feedback(&mut self, output_applied: f32) {
+        self.output = output_applied;
+    }
+
+    /// select low pass on the differentiator channel. No filter is used by default.
+    pub fn set_derivative_filter(&mut self, filter: DerivativeFilter) -> Result<(), Error> {
+        filter.validate()?;
+        self.derivative_filter = filter;
+        Ok(())
+    }
+
+    /// select input of the differentiator channel. Error is used by default.
+    pub fn set_derivative_source(&mut self, source: DerivativeSource) {
+        self.derivative_source = source;
+    }
+
+    /// set setpoint weights b of the proportional and c of the differentiator channel. Both are 1
+    /// by default. Used by [PID::update_setpoint] and [PID::update_2dof].
+    pub fn set_setpoint_weights(&mut self, b: f32, c: f32) -> Result<(), Error> {
+        if !b.is_finite() || !c.is_finite() {
+            return Err(Error::NonFinite);
+        }
+        self.b = b;
+        self.c = c;
+        Ok(())
+    }
+
+    /// update controller with new controller error value. The differentiator works on the error,
+    /// whatever its source is set to.
     pub fn update(&mut self, error: f32) -> f32 {
+        self.step(error, error, error, 0f32)
+    }
+
+    /// update controller with setpoint and measurement instead of their difference. The
+    /// differentiator works on the selected source.
+    pub fn update_setpoint(&mut self, setpoint: f32, measurement: f32) -> f32 {
+        self.update_2dof(setpoint, measurement, 0f32)
+    }
+
+    /// update two degree of freedom controller with setpoint, measurement and feedforward. The
+    /// proportional channel sees b * setpoint - measurement, the differentiator c * setpoint -
+    /// measurement. Feedforward is added to the output before limiting.
+    pub fn update_2dof(&mut self, setpoint: f32, measurement: f32, feedforward: f32) -> f32 {
+        let c = match self.derivative_source {
+            DerivativeSource::Error => self.c,
+            DerivativeSource::Measurement => 0f32,
+        };
+        self.step(
+            setpoint - measurement,
+            self.b * setpoint - measurement,
+            c * setpoint - measurement,
+            feedforward,
+        )
+    }
+
+    /// run controller on error with separate proportional and differentiator inputs
+    fn step(&mut self, error: f32, p_input: f32, d_input: f32, feedforward: f32) -> f32 {
         // I channel
         if self.config.K_i != 0f32 {
-            self.i_chan += self.config.K_i * error;
-            // anti windup
-            if self.i_chan > self.config.limit_high {
-                self.i_chan = self.config.limit_high;
-            } else if self.i_chan < self.config.limit_low {
-                self.i_chan = self.config.limit_low;
+            // saturation of the previous output, limited minus unlimited
+            let saturation = self.output - self.output_unlimited;
+            match self.anti_windup {
+                AntiWindup::Clamping => {
+                    self.i_chan += self.config.K_i * error;
+                    if self.i_chan > self.config.limit_high {
+                        self.i_chan = self.config.limit_high;
+                    } else if self.i_chan < self.config.limit_low {
+                        self.i_chan = self.config.limit_low;
+                    }
+                }
+                AntiWindup::ConditionalIntegration => {
+                    // saturation and error pulling the same way means winding up
+                    if saturation * self.config.K_p * error >= 0f32 {
+                        self.i_chan += self.config.K_i * error;
+                    }
+                }
+                AntiWindup::BackCalculation { K_t } => {
+                    self.i_chan += self.config.K_i * error;
+                    if self.config.K_p != 0f32 {
+                        self.i_chan += K_t / self.f_sampling * saturation / self.config.K_p;
+                    }
+                }
             }
         } else {
             self.i_chan = 0f32;
         }
 
-        // D channel
-        let d = self.config.K_d * (error - self.d_chan);
-        self.d_chan = error;
+        self.derivative(d_input);
+        self.p_recent = p_input;
+        self.feedforward_recent = feedforward;
 
         // final amp
-        self.output = (self.i_chan + d + error) * self.config.K_p;
+        self.output_unlimited =
+            (self.i_chan + self.d_out + p_input) * self.config.K_p + feedforward;
 
         // limit output
-        if self.output > self.config.limit_high {
-            self.output = self.config.limit_high;
-        } else if self.output < self.config.limit_low {
-            self.output = self.config.limit_low;
-        }
+        self.output = self
+            .output_unlimited
+            .clamp(self.config.limit_low, self.config.limit_high);
 
         self.output
     }
 
+    /// D channel, backward euler of K_d s / (1 + T_f s)
+    fn derivative(&mut self, d_input: f32) {
+        let t_sample = 1f32 / self.f_sampling;
+        let T_f = self.derivative_filter.time_constant(self.config.K_d);
+        self.d_out =
+            (T_f * self.d_out + self.config.K_d * (d_input - self.d_chan)) / (T_f + t_sample);
+        self.d_chan = d_input;
+    }
+
+    /// set integrator so that the most recent inputs result in the given unlimited output
+    fn back_calculate(&mut self, output_unlimited: f32) {
+        if self.config.K_p != 0f32 {
+            self.i_chan = (output_unlimited - self.feedforward_recent) / self.config.K_p
+                - self.d_out
+                - self.p_recent;
+        }
+        self.output_unlimited = output_unlimited;
+    }
+
     /// reset states in pid controller to given values
     pub fn reset(&mut self, i_channel: f32, d_channel: f32) {
         self.i_chan = i_channel;
         self.d_chan = d_channel;
+        self.d_out = 0f32;
+        self.output_unlimited = self.output;
+    }
+}
+
+/// incremental pid controller, a.k.a. velocity form. Calculates the change of the output instead
+/// of the output itself, so there's no integrator to wind up.
+#[derive(PartialEq, Debug)]
+pub struct IncrementalPID {
+    /// most recent error
+    error_recent: f32,
+    /// most recent filtered differentiator output
+    d_out: f32,
+    /// accumulated output
+    output: f32,
+    /// differentiator filter
+    derivative_filter: DerivativeFilter,
+    /// sampling frequency
+    f_sampling: f32,
+    /// pid configuration as given on construction
+    config: PIDConfig,
+}
+
+impl IncrementalPID {
+    /// create new incremental pid controller from config
+    pub fn new(cfg: PIDConfig, f_sampling_Hz: f32) -> IncrementalPID {
+        IncrementalPID {
+            error_recent: 0f32,
+            d_out: 0f32,
+            output: 0f32,
+            derivative_filter: DerivativeFilter::None,
+            f_sampling: f_sampling_Hz,
+            config: cfg,
+        }
+    }
+
+    /// create new incremental pid controller from config after validating config and sampling
+    /// frequency
+    pub fn try_new(cfg: PIDConfig, f_sampling_Hz: f32) -> Result<IncrementalPID, Error> {
+        cfg.validate()?;
+        if !f_sampling_Hz.is_finite() || f_sampling_Hz <= 0f32 {
+            return Err(Error::InvalidSampleRate);
+        }
+        Ok(IncrementalPID::new(cfg, f_sampling_Hz))
+    }
+
+    /// configuration as it was given on construction
+    pub fn config(&self) -> PIDConfig {
+        self.config
+    }
+
+    /// replace configuration at runtime. The output continues where it is.
+    pub fn set_config(&mut self, cfg: PIDConfig) -> Result<(), Error> {
+        cfg.validate()?;
+        self.config = cfg;
+        Ok(())
+    }
+
+    /// select low pass on the differentiator channel. No filter is used by default.
+    pub fn set_derivative_filter(&mut self, filter: DerivativeFilter) -> Result<(), Error> {
+        filter.validate()?;
+        self.derivative_filter = filter;
+        Ok(())
+    }
+
+    /// update controller with new controller error value. Returns the change of the output,
+    /// limited so that the accumulated output stays within the limits.
+    pub fn update(&mut self, error: f32) -> f32 {
+        let t_sample = 1f32 / self.f_sampling;
+
+        // D channel, backward euler of K_d s / (1 + T_f s)
+        let T_f = self.derivative_filter.time_constant(self.config.K_d);
+        let d_out =
+            (T_f * self.d_out + self.config.K_d * (error - self.error_recent)) / (T_f + t_sample);
+
+        let delta = self.config.K_p
+            * (error - self.error_recent + self.config.K_i * t_sample * error + d_out - self.d_out);
+        self.error_recent = error;
+        self.d_out = d_out;
+
+        let output = (self.output + delta).clamp(self.config.limit_low, self.config.limit_high);
+        let delta = output - self.output;
+        self.output = output;
+
+        delta
+    }
+
+    /// accumulated output
+    pub fn output(&self) -> f32 {
+        self.output
+    }
+
+    /// continue from an output given by someone else, like a manual mode
+    pub fn track(&mut self, output: f32) {
+        self.output = output;
+    }
+}
+
+/// scale integrator gain of config with sampling frequency
+fn scale_config(cfg: PIDConfig, f_sampling_Hz: f32) -> PIDConfig {
+    PIDConfig {
+        K_i: cfg.K_i / f_sampling_Hz,
+        ..cfg
     }
 }
 
@@ -166,4 +619,309 @@ mod tests {
         pid.reset(50f32, 50f32);
         assert_eq!(pid.update(0f32), -50f32);
     }
+
+    #[test]
+    fn derivative_scaling() {
+        // error ramp of 1 per second: D part is K_d * 1, whatever the sampling frequency
+        for f_sampling in [100f32, 1000f32, 20000f32] {
+            let cfg = PIDConfig::new(1f32, 0f32, 0.5f32, (-100f32, 100f32)).unwrap();
+            let mut pid = PID::new(cfg, f_sampling);
+            pid.update(0f32);
+            let error = 1f32 / f_sampling;
+            assert!(float_cmp::approx_eq!(
+                f32,
+                pid.update(error) - error,
+                0.5f32,
+                epsilon = 1e-3
+            ));
+        }
+    }
+
+    #[test]
+    fn config_validation() {
+        assert!(PIDConfig::new(1f32, 1f32, 0f32, (-10f32, 10f32)).is_ok());
+        assert_eq!(
+            PIDConfig::new(f32::NAN, 1f32, 0f32, (-10f32, 10f32)),
+            Err(Error::NonFinite)
+        );
+        assert_eq!(
+            PIDConfig::new(1f32, 1f32, 0f32, (-10f32, f32::INFINITY)),
+            Err(Error::NonFinite)
+        );
+        assert_eq!(
+            PIDConfig::new(1f32, 1f32, 0f32, (10f32, -10f32)),
+            Err(Error::InvalidLimits)
+        );
+
+        let cfg = PIDConfig::new(1f32, 1f32, 0f32, (-10f32, 10f32)).unwrap();
+        assert_eq!(PID::try_new(cfg, 0f32), Err(Error::InvalidSampleRate));
+        assert_eq!(PID::try_new(cfg, -1000f32), Err(Error::InvalidSampleRate));
+        assert!(PID::try_new(cfg, 1000f32).is_ok());
+    }
+
+    #[test]
+    fn retune() {
+        let cfg = PIDConfig::new(1f32, 1f32, 0f32, (-100f32, 100f32)).unwrap();
+        let mut pid = PID::try_new(cfg, 1f32).unwrap();
+        assert_eq!(pid.update(1f32), 2f32);
+        assert_eq!(pid.config(), cfg);
+
+        // integrator state survives the new gains
+        pid.set_gains(2f32, 1f32, 0f32).unwrap();
+        assert_eq!(pid.update(0f32), 2f32);
+        assert_eq!(pid.config().K_p(), 2f32);
+        assert_eq!(pid.config().limits(), (-100f32, 100f32));
+
+        // invalid gains are refused and the old ones kept
+        assert_eq!(pid.set_gains(f32::NAN, 1f32, 0f32), Err(Error::NonFinite));
+        assert_eq!(pid.config().K_p(), 2f32);
+    }
+
+    /// step response of an integrating plant, like a speed loop, with a PI controller whose
+    /// output gets clipped downstream. Returns the overshoot.
+    fn overshoot(anti_windup: AntiWindup, feedback: bool) -> f32 {
+        let cfg = PIDConfig::new(2f32, 20f32, 0f32, (-100f32, 100f32)).unwrap();
+        let mut pid = PID::try_new(cfg, 1000f32).unwrap();
+        pid.set_anti_windup(anti_windup).unwrap();
+
+        let mut speed = 0f32;
+        let mut speed_max = 0f32;
+        for _ in 0..5000 {
+            let output = pid.update(100f32 - speed);
+            // current limit
+            let output_applied = output.clamp(-10f32, 10f32);
+            if feedback {
+                pid.saturation_feedback(output_applied);
+            }
+            speed += output_applied * 10f32 / 1000f32;
+            speed_max = speed_max.max(speed);
+        }
+        assert!(float_cmp::approx_eq!(f32, speed, 100f32, epsilon = 0.5));
+        speed_max - 100f32
+    }
+
+    #[test]
+    fn anti_windup() {
+        let clamping = overshoot(AntiWindup::Clamping, true);
+        let conditional = overshoot(AntiWindup::ConditionalIntegration, true);
+        let back_calculation = overshoot(AntiWindup::BackCalculation { K_t: 20f32 }, true);
+        let blind = overshoot(AntiWindup::BackCalculation { K_t: 20f32 }, false);
+
+        assert!(clamping > 20f32);
+        assert!(conditional < 0.2f32 * clamping);
+        assert!(back_calculation < 0.2f32 * clamping);
+        // without feedback the controller doesn't know about the downstream limit
+        assert!(blind > 0.5f32 * clamping);
+
+        let cfg = PIDConfig::new(1f32, 1f32, 0f32, (-1f32, 1f32)).unwrap();
+        let mut pid = PID::new(cfg, 1000f32);
+        assert_eq!(
+            pid.set_anti_windup(AntiWindup::BackCalculation { K_t: -1f32 }),
+            Err(Error::InvalidTrackingGain)
+        );
+    }
+
+    #[test]
+    fn derivative_filter() {
+        let cfg = PIDConfig::new(1f32, 0f32, 0.01f32, (-100f32, 100f32)).unwrap();
+        let mut raw = PID::new(cfg, 1000f32);
+        let mut filtered = PID::new(cfg, 1000f32);
+        filtered
+            .set_derivative_filter(DerivativeFilter::Cutoff(10f32))
+            .unwrap();
+
+        // ramp of 1 per second: D part settles to K_d * 1
+        for n in 0..100 {
+            raw.update(n as f32 / 1000f32);
+            filtered.update(n as f32 / 1000f32);
+        }
+        assert!(float_cmp::approx_eq!(
+            f32,
+            raw.update(0.1f32) - 0.1f32,
+            0.01f32,
+            epsilon = 1e-4
+        ));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            filtered.update(0.1f32) - 0.1f32,
+            0.01f32,
+            epsilon = 1e-4
+        ));
+
+        // alternating noise of one ADC count gets damped
+        let noise_raw = raw.update(0.1f32 + 1e-3f32) - raw.update(0.1f32);
+        let noise_filtered = filtered.update(0.1f32 + 1e-3f32) - filtered.update(0.1f32);
+        assert!(noise_filtered.abs() < 0.2f32 * noise_raw.abs());
+
+        assert_eq!(
+            filtered.set_derivative_filter(DerivativeFilter::N(0f32)),
+            Err(Error::InvalidDerivativeFilter)
+        );
+    }
+
+    #[test]
+    fn derivative_on_measurement() {
+        let cfg = PIDConfig::new(1f32, 0f32, 0.01f32, (-100f32, 100f32)).unwrap();
+        let mut on_error = PID::new(cfg, 1000f32);
+        let mut on_measurement = PID::new(cfg, 1000f32);
+        on_measurement.set_derivative_source(DerivativeSource::Measurement);
+
+        // setpoint step kicks only the error derivative
+        assert!(float_cmp::approx_eq!(
+            f32,
+            on_error.update_setpoint(1f32, 0f32),
+            11f32,
+            epsilon = 1e-4
+        ));
+        assert_eq!(on_measurement.update_setpoint(1f32, 0f32), 1f32);
+
+        // but both react to the measurement moving
+        assert!(float_cmp::approx_eq!(
+            f32,
+            on_measurement.update_setpoint(1f32, 0.1f32),
+            -0.1f32,
+            epsilon = 1e-4
+        ));
+    }
+
+    #[test]
+    fn two_dof() {
+        let cfg = PIDConfig::new(2f32, 10f32, 0f32, (-100f32, 100f32)).unwrap();
+        let mut pid = PID::new(cfg, 1000f32);
+
+        // without setpoint weight the step only reaches the output through the integrator
+        pid.set_setpoint_weights(0f32, 0f32).unwrap();
+        assert!(float_cmp::approx_eq!(
+            f32,
+            pid.update_2dof(10f32, 0f32, 0f32),
+            0.2f32,
+            epsilon = 1e-5
+        ));
+        // measurement still acts proportionally
+        assert!(float_cmp::approx_eq!(
+            f32,
+            pid.update_2dof(10f32, 1f32, 0f32),
+            2f32 * (0.19f32 - 1f32),
+            epsilon = 1e-5
+        ));
+
+        // feedforward is limited together with the controller output
+        pid.set_setpoint_weights(1f32, 0f32).unwrap();
+        assert_eq!(pid.update_2dof(100f32, 0f32, 90f32), 100f32);
+
+        assert_eq!(
+            pid.set_setpoint_weights(f32::NAN, 0f32),
+            Err(Error::NonFinite)
+        );
+    }
+
+    #[test]
+    fn feedforward_anti_windup() {
+        let cfg = PIDConfig::new(1f32, 100f32, 0f32, (-10f32, 10f32)).unwrap();
+        let mut pid = PID::new(cfg, 1000f32);
+        pid.set_anti_windup(AntiWindup::ConditionalIntegration)
+            .unwrap();
+
+        // feedforward alone saturates the output, so the integrator must not wind up
+        for _ in 0..1000 {
+            assert_eq!(pid.update_2dof(1f32, 0f32, 20f32), 10f32);
+        }
+        // as soon as feedforward drops, the controller is back to normal
+        assert!(pid.update_2dof(1f32, 0f32, 0f32) < 1.5f32);
+    }
+
+    #[test]
+    fn tracking() {
+        let cfg = PIDConfig::new(2f32, 10f32, 0.01f32, (-100f32, 100f32)).unwrap();
+        let mut pid = PID::new(cfg, 1000f32);
+
+        // open loop startup drives the output, while the error moves around
+        for n in 0..100 {
+            pid.track(n as f32 * 0.1f32, 5f32 - n as f32 * 0.01f32);
+        }
+        // handover continues at the tracked output
+        let output = pid.update(4f32);
+        assert!(float_cmp::approx_eq!(f32, output, 9.9f32, epsilon = 0.1));
+
+        // setpoint weights and derivative source don't matter for the error alone
+        let mut pid = PID::new(cfg, 1000f32);
+        pid.set_setpoint_weights(0.5f32, 0f32).unwrap();
+        pid.set_derivative_source(DerivativeSource::Measurement);
+        for _ in 0..100 {
+            pid.track(5f32, 2f32);
+        }
+        assert!(float_cmp::approx_eq!(
+            f32,
+            pid.update(2f32),
+            5f32 + 2f32 * 10f32 * 2f32 / 1000f32,
+            epsilon = 1e-4
+        ));
+    }
+
+    #[test]
+    fn bumpless_gains() {
+        let cfg = PIDConfig::new(2f32, 10f32, 0f32, (-100f32, 100f32)).unwrap();
+        let mut pid = PID::new(cfg, 1000f32);
+        let mut jumpy = PID::new(cfg, 1000f32);
+        for _ in 0..100 {
+            pid.update(5f32);
+            jumpy.update(5f32);
+        }
+        let output = pid.update(5f32);
+        jumpy.update(5f32);
+
+        pid.set_gains_bumpless(4f32, 20f32, 0f32).unwrap();
+        jumpy.set_gains(4f32, 20f32, 0f32).unwrap();
+        assert_eq!(pid.config().K_p, 4f32);
+
+        // only the new integration step is added
+        assert!(float_cmp::approx_eq!(
+            f32,
+            pid.update(5f32),
+            output + 4f32 * 20f32 * 5f32 / 1000f32,
+            epsilon = 1e-3
+        ));
+        assert!(jumpy.update(5f32) - output > 10f32);
+
+        assert_eq!(
+            pid.set_gains_bumpless(0f32, 20f32, 0f32),
+            Err(Error::ZeroGain)
+        );
+        assert_eq!(pid.config().K_p, 4f32);
+    }
+
+    #[test]
+    fn incremental() {
+        let cfg = PIDConfig::new(2f32, 10f32, 0.01f32, (-10f32, 10f32)).unwrap();
+        let mut positional = PID::new(cfg, 1000f32);
+        let mut incremental = IncrementalPID::try_new(cfg, 1000f32).unwrap();
+        positional
+            .set_derivative_filter(DerivativeFilter::N(10f32))
+            .unwrap();
+        incremental
+            .set_derivative_filter(DerivativeFilter::N(10f32))
+            .unwrap();
+
+        // same as positional form as long as nothing saturates
+        for n in 0..100 {
+            let error = (n as f32 * 0.1f32).sin();
+            let output = positional.update(error);
+            incremental.update(error);
+            assert!(float_cmp::approx_eq!(
+                f32,
+                incremental.output(),
+                output,
+                epsilon = 1e-4
+            ));
+        }
+
+        // saturated for a long time
+        for _ in 0..1000 {
+            incremental.update(100f32);
+        }
+        assert_eq!(incremental.output(), 10f32);
+        // but leaves the limit as soon as the error turns
+        assert!(incremental.update(-1f32) < 0f32);
+        assert!(incremental.output() < 10f32);
+    }
 }
@@ -1,7 +1,8 @@
 #![deny(unsafe_code)]
 #![deny(missing_docs)]
+#![allow(non_snake_case)]
 
-//! PLL module. This function in here serves the purpose of sensorless motor state estimation from
+//! PLL module. [Pll] in here serves the purpose of sensorless motor state estimation from
 //! electrical data. So far it's scope is limited to passive voltage and current sensing. More
 //! clealy, it excludes [high frequency
 //! injection](https://ieeexplore.ieee.org/abstract/document/5316521).
@@ -24,34 +25,238 @@
 //! algorithm and start your normal control path.
 //!
 //! Second problem is - as you've guessed - already solved, but not in this straight foreward way
-//! you'd wish.  On the other side it has given this modules name. We use a PID control block, feed
-//! it with the dq-transformed V_ind you calculated previously - esprecially the d part. Your V_ind
-//! should not have a real ingredient, so whenever you see one, you change your output speed
-//! accordingly. Your rotor's position is then only a matter of integration over time.
+//! you'd wish.  On the other side it has given this modules name. We calculate V_ind as
+//! V_1 - R I - L dI/dt, and use a PI control block, fed with the dq-transformed V_ind - esprecially
+//! the d part. Your V_ind should not have a real ingredient, so whenever you see one, you change
+//! your output speed accordingly. Your rotor's position is then only a matter of integration over time.
 //! Is that accurately measured? No! But don't bother. Since your output voltage uses your
 //! calculated rotor speed and position, your motor keeps itself controlled. Of course, the more
 //! accurate your speed and rotor position, the better your efficiency. In most cases you'll be ok.
 //! Hopefully!
 //!
+//! Your inverter measures in abc? Transform with [crate::dq::abc2ab] first.
 
-use crate::dq::abc2ab;
-use crate::motor::Motor;
+use crate::dq::ab2dq;
+use crate::motor::{wrap_angle, Config, Mechanical};
+use crate::pid::{self, PIDConfig, PID};
 use num::complex::c32;
+use num::Complex;
+#[cfg_attr(test, allow(unused_imports))]
+use num_traits::Float;
 
-/// extract rotor state information from electrial data
-/// TODO: check if it's a good idea to consume mutabl borrow of motor state instead
-pub fn pll(motor: &mut Motor, v_1_abc: [f32; 3], i_abc: [f32; 3]) {
-    // transform input to ab
-    let v_ab = abc2ab(v_1_abc);
-    let i_ab = abc2ab(i_abc);
-    // calc V_z
-    let z = c32(
-        motor.cfg.resistance,
-        motor.cfg.inductance.im * motor.mech.speed,
-    );
-    let v_z = i_ab * z;
-    // subtract V_z from V_1 to get V_ind
-    let v_ind = v_ab - v_z;
-    // feed pll with V_ind.re to create feedback loop
-    // integrate rotor speed for angle
+/// pll observer errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// bandwidth or damping of the pll is not positive and finite
+    InvalidLoop,
+    /// speed limit or sampling frequency don't make a valid PI controller
+    InvalidConfig(pid::Error),
+}
+
+/// configuration struct for the back-EMF pll observer
+#[derive(PartialEq, Debug)]
+pub struct PllConfig {
+    /// natural frequency of the pll in rad per second
+    pub bandwidth: f32,
+    /// damping of the pll, 1 for critical damping
+    pub damping: f32,
+    /// maximum tracked electrical speed in rad per second
+    pub speed_max: f32,
+}
+
+/// back-EMF pll observer for the electrical rotor state
+#[derive(PartialEq, Debug)]
+pub struct Pll {
+    /// estimated electrical angle
+    angle: f32,
+    /// estimated electrical speed
+    speed: f32,
+    /// induced voltage in ab of the most recent update
+    v_ind: Complex<f32>,
+    /// current in ab of the most recent update for the inductive voltage drop
+    i_ab_recent: Complex<f32>,
+    /// PI controller of the loop
+    pi: PID,
+    /// proportional gain of the PI controller
+    K_p: f32,
+    /// sampling frequency
+    f_sample: f32,
+}
+
+impl Pll {
+    /// create new pll observer at standstill
+    pub fn new(cfg: PllConfig, f_sample_Hz: f32) -> Result<Pll, Error> {
+        let positive = |value: f32| value.is_finite() && value > 0f32;
+        if !positive(cfg.bandwidth) || !positive(cfg.damping) {
+            return Err(Error::InvalidLoop);
+        }
+        // speed = K_p * (e + K_i * ∫e) with K_p = 2 ζ ω_n and K_p * K_i = ω_n²
+        let K_p = 2f32 * cfg.damping * cfg.bandwidth;
+        let pi_cfg = PIDConfig::new(
+            K_p,
+            cfg.bandwidth / (2f32 * cfg.damping),
+            0f32,
+            (-cfg.speed_max, cfg.speed_max),
+        )
+        .map_err(Error::InvalidConfig)?;
+        let pi = PID::try_new(pi_cfg, f_sample_Hz).map_err(Error::InvalidConfig)?;
+        Ok(Pll {
+            angle: 0f32,
+            speed: 0f32,
+            v_ind: c32(0f32, 0f32),
+            i_ab_recent: c32(0f32, 0f32),
+            pi,
+            K_p,
+            f_sample: f_sample_Hz,
+        })
+    }
+
+    /// run this method on every sample with stator voltage and current in ab. Returns the
+    /// estimated electrical rotor state.
+    pub fn update(&mut self, cfg: &Config, v_ab: Complex<f32>, i_ab: Complex<f32>) -> Mechanical {
+        // predict angle for this sample
+        self.angle = wrap_angle(self.angle + self.speed / self.f_sample);
+
+        // V_ind = V_1 - R I - L dI/dt
+        let di_dt = (i_ab - self.i_ab_recent) * self.f_sample;
+        self.i_ab_recent = i_ab;
+        self.v_ind = v_ab - i_ab * cfg.resistance - di_dt * cfg.inductance.im;
+
+        // V_ind lies on the q axis, its d part is the angle error
+        let v_ind_dq = ab2dq(self.v_ind, self.angle);
+        let v_ind_abs = self.v_ind.norm_sqr().sqrt();
+        let error = if v_ind_abs > f32::EPSILON {
+            -v_ind_dq.re * v_ind_dq.im.signum() / v_ind_abs
+        } else {
+            0f32
+        };
+        let speed = self.pi.update(error);
+        let acceleration = (speed - self.speed) * self.f_sample;
+        self.speed = speed;
+
+        Mechanical {
+            angle: self.angle,
+            speed,
+            acceleration,
+        }
+    }
+
+    /// most recent estimated electrical angle
+    pub fn angle(&self) -> f32 {
+        self.angle
+    }
+
+    /// amplitude of the most recent induced voltage. The angle is only as good as this is large.
+    pub fn bemf(&self) -> f32 {
+        self.v_ind.norm_sqr().sqrt()
+    }
+
+    /// move estimated angle by a correction, e.g. from a hall edge
+    pub fn correct_angle(&mut self, correction: f32) {
+        self.angle = wrap_angle(self.angle + correction);
+    }
+
+    /// reset pll to given electrical angle and speed
+    pub fn reset(&mut self, angle: f32, speed: f32) {
+        self.angle = wrap_angle(angle);
+        self.speed = speed;
+        // speed is K_p times the integrator channel in steady state
+        self.pi.reset(speed / self.K_p, 0f32);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::motor::wrap_angle_diff;
+
+    const F_SAMPLE: f32 = 10000f32;
+
+    /// motor for the observer tests
+    pub(crate) fn motor_config() -> Config {
+        Config {
+            resistance: 0.5f32,
+            inductance: c32(1e-3f32, 1e-3f32),
+            flux: 0.01f32,
+            inertia: 1e-4f32,
+        }
+    }
+
+    /// voltage and current in ab of a motor at electrical angle and speed with q current
+    pub(crate) fn motor_ab(
+        cfg: &Config,
+        angle: f32,
+        speed: f32,
+        i_q: f32,
+    ) -> (Complex<f32>, Complex<f32>) {
+        let rot = c32(angle.cos(), angle.sin());
+        let i_ab = c32(0f32, i_q) * rot;
+        let di_dt = c32(0f32, i_q) * c32(0f32, speed) * rot;
+        let v_ind = c32(0f32, speed * cfg.flux) * rot;
+        let v_ab = i_ab * cfg.resistance + di_dt * cfg.inductance.im + v_ind;
+        (v_ab, i_ab)
+    }
+
+    #[test]
+    fn pll_tracking() {
+        let cfg = motor_config();
+        let mut pll = Pll::new(
+            PllConfig {
+                bandwidth: 200f32,
+                damping: 1f32,
+                speed_max: 5000f32,
+            },
+            F_SAMPLE,
+        )
+        .unwrap();
+
+        let speed = 1000f32;
+        let mut angle = 0.5f32;
+        let mut state = pll.update(&cfg, c32(0f32, 0f32), c32(0f32, 0f32));
+        for _ in 0..5000 {
+            angle = wrap_angle(angle + speed / F_SAMPLE);
+            let (v_ab, i_ab) = motor_ab(&cfg, angle, speed, 2f32);
+            state = pll.update(&cfg, v_ab, i_ab);
+        }
+
+        let angle_diff = wrap_angle_diff(state.angle - angle);
+        assert!(float_cmp::approx_eq!(f32, angle_diff, 0f32, epsilon = 0.01));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            state.speed,
+            speed,
+            epsilon = 1f32
+        ));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            pll.bemf(),
+            speed * cfg.flux,
+            epsilon = 0.1
+        ));
+    }
+
+    #[test]
+    fn invalid_config() {
+        let cfg = |bandwidth: f32, damping: f32, speed_max: f32| PllConfig {
+            bandwidth,
+            damping,
+            speed_max,
+        };
+        assert_eq!(
+            Pll::new(cfg(200f32, 0f32, 5000f32), F_SAMPLE),
+            Err(Error::InvalidLoop)
+        );
+        assert_eq!(
+            Pll::new(cfg(-200f32, 1f32, 5000f32), F_SAMPLE),
+            Err(Error::InvalidLoop)
+        );
+        assert_eq!(
+            Pll::new(cfg(200f32, 1f32, -5000f32), F_SAMPLE),
+            Err(Error::InvalidConfig(pid::Error::InvalidLimits))
+        );
+        assert_eq!(
+            Pll::new(cfg(200f32, 1f32, 5000f32), 0f32),
+            Err(Error::InvalidConfig(pid::Error::InvalidSampleRate))
+        );
+    }
 }
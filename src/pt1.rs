@@ -4,12 +4,49 @@
 
 //! rust library for generic motor control of pmdc motors
 
+use crate::dq::{ab2dq, dq2ab};
+use num::complex::c32;
+use num::Complex;
+#[cfg_attr(test, allow(unused_imports))]
+use num_traits::Float;
+
+/// PT1 errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// time constant is not positive and finite
+    InvalidTimeConstant,
+    /// sampling frequency is not positive and finite, or time between updates is negative
+    InvalidSampleTime,
+}
+
+/// discretization of the continuous PT1 K / (1 + T s)
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Discretization {
+    /// forward euler. Cheapest, but only accurate for time constants of many sample periods
+    ForwardEuler,
+    /// backward euler. Stable for any time constant, but lags behind
+    BackwardEuler,
+    /// tustin or bilinear transformation. Keeps the shape of the frequency response
+    Tustin,
+    /// exact for inputs held over the sample period, e.g. by a PWM. Needs an exp on every change
+    /// of time constant or sample time
+    ZeroOrderHold,
+}
+
 /// PT1 state
 pub struct PT1 {
-    /// integrator state
-    i_chan: f32,
+    /// most recent input
+    input_recent: f32,
     /// most recent output
     output: f32,
+    /// output coefficient a in y[k] = a y[k-1] + b0 x[k] + b1 x[k-1]
+    a: f32,
+    /// input coefficient b0
+    b0: f32,
+    /// recent input coefficient b1
+    b1: f32,
+    /// sample time
+    t_sample: f32,
     /// configuration
     config: PT1Config,
 }
@@ -17,51 +54,220 @@ pub struct PT1 {
 /// configuration structure for PT1
 pub struct PT1Config {
     /// integration amplifyer
-    K_p: f32,
+    pub K_p: f32,
     /// time constant
-    T: f32,
+    pub T: f32,
+    /// discretization method
+    pub discretization: Discretization,
 }
 
 impl PT1 {
     /// create new PT1
-    pub fn new(cfg: PT1Config, f_sample_Hz: f32) -> PT1 {
-        let newconfig = PT1Config {
-            T: cfg.T * f_sample_Hz,
-            ..cfg
-        };
-
-        PT1 {
-            i_chan: 0f32,
+    pub fn new(cfg: PT1Config, f_sample_Hz: f32) -> Result<PT1, Error> {
+        let t_sample = sample_time(f_sample_Hz)?;
+        let (a, b0, b1) = coefficients(&cfg, t_sample)?;
+        Ok(PT1 {
+            input_recent: 0f32,
             output: 0f32,
-            config: newconfig,
-        }
+            a,
+            b0,
+            b1,
+            t_sample,
+            config: cfg,
+        })
     }
 
     /// update filter with new data
     pub fn update(&mut self, input: f32) -> f32 {
-        self.i_chan -= self.i_chan / self.config.T;
-        self.i_chan += input * self.config.K_p;
-        self.output = self.i_chan / self.config.T;
+        self.output = self.a * self.output + self.b0 * input + self.b1 * self.input_recent;
+        self.input_recent = input;
 
         self.output
     }
 
+    /// update filter with new data that arrived t_delta seconds after the previous one, for
+    /// event driven updates like hall edges. A negative t_delta is refused and the filter left
+    /// as it is.
+    pub fn update_with_time(&mut self, input: f32, t_delta: f32) -> Result<f32, Error> {
+        let (a, b0, b1) = coefficients(&self.config, t_delta)?;
+        self.output = a * self.output + b0 * input + b1 * self.input_recent;
+        self.input_recent = input;
+
+        Ok(self.output)
+    }
+
+    /// change time constant at runtime. The output continues where it is. An invalid time
+    /// constant is refused and the old one kept.
+    pub fn set_time_constant(&mut self, T: f32) -> Result<(), Error> {
+        let cfg = PT1Config { T, ..self.config };
+        (self.a, self.b0, self.b1) = coefficients(&cfg, self.t_sample)?;
+        self.config = cfg;
+        Ok(())
+    }
+
     /// reset filter to given value. if in doublt, use 0
     pub fn reset(&mut self, reset_value: f32) {
-        self.i_chan = reset_value * self.config.T;
         self.output = reset_value;
+        self.input_recent = if self.config.K_p != 0f32 {
+            reset_value / self.config.K_p
+        } else {
+            0f32
+        };
+    }
+}
+
+/// PT1 on complex signals, like currents in dq or voltages in ab. Filters real and imaginary part
+/// alike.
+pub struct ComplexPT1 {
+    /// most recent input
+    input_recent: Complex<f32>,
+    /// most recent output
+    output: Complex<f32>,
+    /// output coefficient a in y[k] = a y[k-1] + b0 x[k] + b1 x[k-1]
+    a: f32,
+    /// input coefficient b0
+    b0: f32,
+    /// recent input coefficient b1
+    b1: f32,
+    /// sample time
+    t_sample: f32,
+    /// configuration
+    config: PT1Config,
+}
+
+impl ComplexPT1 {
+    /// create new complex PT1
+    pub fn new(cfg: PT1Config, f_sample_Hz: f32) -> Result<ComplexPT1, Error> {
+        let t_sample = sample_time(f_sample_Hz)?;
+        let (a, b0, b1) = coefficients(&cfg, t_sample)?;
+        Ok(ComplexPT1 {
+            input_recent: c32(0f32, 0f32),
+            output: c32(0f32, 0f32),
+            a,
+            b0,
+            b1,
+            t_sample,
+            config: cfg,
+        })
+    }
+
+    /// update filter with new data
+    pub fn update(&mut self, input: Complex<f32>) -> Complex<f32> {
+        self.output = self.output * self.a + input * self.b0 + self.input_recent * self.b1;
+        self.input_recent = input;
+
+        self.output
+    }
+
+    /// update filter with new data that arrived t_delta seconds after the previous one. A
+    /// negative t_delta is refused and the filter left as it is.
+    pub fn update_with_time(
+        &mut self,
+        input: Complex<f32>,
+        t_delta: f32,
+    ) -> Result<Complex<f32>, Error> {
+        let (a, b0, b1) = coefficients(&self.config, t_delta)?;
+        self.output = self.output * a + input * b0 + self.input_recent * b1;
+        self.input_recent = input;
+
+        Ok(self.output)
+    }
+
+    /// change time constant at runtime. The output continues where it is. An invalid time
+    /// constant is refused and the old one kept.
+    pub fn set_time_constant(&mut self, T: f32) -> Result<(), Error> {
+        let cfg = PT1Config { T, ..self.config };
+        (self.a, self.b0, self.b1) = coefficients(&cfg, self.t_sample)?;
+        self.config = cfg;
+        Ok(())
+    }
+
+    /// reset filter to given value. if in doubt, use 0
+    pub fn reset(&mut self, reset_value: Complex<f32>) {
+        self.output = reset_value;
+        self.input_recent = if self.config.K_p != 0f32 {
+            reset_value / self.config.K_p
+        } else {
+            c32(0f32, 0f32)
+        };
+    }
+}
+
+/// synchronous frame filter. Rotates the input by the given angle into a rotating frame, filters it
+/// there with a PT1 and rotates it back. Whatever spins with the angle, like the fundamental of a
+/// current in ab, is constant in that frame and passes without phase lag. Everything else, like
+/// harmonics or noise, is filtered.
+pub struct SynchronousPT1 {
+    /// filter in the rotating frame
+    pt1: ComplexPT1,
+}
+
+impl SynchronousPT1 {
+    /// create new synchronous frame filter
+    pub fn new(cfg: PT1Config, f_sample_Hz: f32) -> Result<SynchronousPT1, Error> {
+        Ok(SynchronousPT1 {
+            pt1: ComplexPT1::new(cfg, f_sample_Hz)?,
+        })
+    }
+
+    /// update filter with new data in the stationary frame and the angle of the rotating frame.
+    /// Returns the filtered signal in the stationary frame.
+    pub fn update(&mut self, input: Complex<f32>, angle: f32) -> Complex<f32> {
+        let rotating = self.pt1.update(ab2dq(input, angle));
+        dq2ab(rotating, angle)
+    }
+
+    /// reset filter to given value in the rotating frame. if in doubt, use 0
+    pub fn reset(&mut self, reset_value: Complex<f32>) {
+        self.pt1.reset(reset_value);
     }
 }
 
+/// sample time of a positive finite sampling frequency
+fn sample_time(f_sample_Hz: f32) -> Result<f32, Error> {
+    if !f_sample_Hz.is_finite() || f_sample_Hz <= 0f32 {
+        return Err(Error::InvalidSampleTime);
+    }
+    Ok(1f32 / f_sample_Hz)
+}
+
+/// coefficients a, b0 and b1 for a sample time, checking the time constant and sample time first
+fn coefficients(config: &PT1Config, t_sample: f32) -> Result<(f32, f32, f32), Error> {
+    let K = config.K_p;
+    let T = config.T;
+    if !T.is_finite() || T <= 0f32 {
+        return Err(Error::InvalidTimeConstant);
+    }
+    if !t_sample.is_finite() || t_sample < 0f32 {
+        return Err(Error::InvalidSampleTime);
+    }
+    Ok(match config.discretization {
+        Discretization::ForwardEuler => (1f32 - t_sample / T, K * t_sample / T, 0f32),
+        Discretization::BackwardEuler => (T / (T + t_sample), K * t_sample / (T + t_sample), 0f32),
+        Discretization::Tustin => {
+            let b = K * t_sample / (2f32 * T + t_sample);
+            ((2f32 * T - t_sample) / (2f32 * T + t_sample), b, b)
+        }
+        Discretization::ZeroOrderHold => {
+            let alpha = (-t_sample / T).exp();
+            (alpha, K * (1f32 - alpha), 0f32)
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn pt1() {
-        let cfg = PT1Config { K_p: 1f32, T: 1f32 };
+        let cfg = PT1Config {
+            K_p: 1f32,
+            T: 1f32,
+            discretization: Discretization::ForwardEuler,
+        };
 
-        let mut pt1 = PT1::new(cfg, 1000f32);
+        let mut pt1 = PT1::new(cfg, 1000f32).unwrap();
 
         // test initial value
         assert_eq!(pt1.output, 0f32);
@@ -78,4 +284,164 @@ mod tests {
             epsilon = 0.001
         ));
     }
+
+    /// step response after n samples with a time constant of 3 samples
+    fn step(discretization: Discretization, n: usize) -> f32 {
+        let cfg = PT1Config {
+            K_p: 2f32,
+            T: 3e-3f32,
+            discretization,
+        };
+        let mut pt1 = PT1::new(cfg, 1000f32).unwrap();
+        (0..n).fold(0f32, |_, _| pt1.update(1f32))
+    }
+
+    #[test]
+    fn discretization() {
+        // held step is exact, even for short time constants
+        let exact = 2f32 * (1f32 - (-1f32).exp());
+        assert!(float_cmp::approx_eq!(
+            f32,
+            step(Discretization::ZeroOrderHold, 3),
+            exact,
+            epsilon = 1e-5
+        ));
+        // the others get close and settle on the same value
+        for discretization in [
+            Discretization::ForwardEuler,
+            Discretization::BackwardEuler,
+            Discretization::Tustin,
+        ] {
+            assert!((step(discretization, 3) - exact).abs() < 0.25f32);
+            assert!(float_cmp::approx_eq!(
+                f32,
+                step(discretization, 100),
+                2f32,
+                epsilon = 1e-4
+            ));
+        }
+    }
+
+    #[test]
+    fn variable_time() {
+        let cfg = PT1Config {
+            K_p: 1f32,
+            T: 0.01f32,
+            discretization: Discretization::ZeroOrderHold,
+        };
+        let mut pt1 = PT1::new(cfg, 1000f32).unwrap();
+
+        // irregular events add up to the same exact response
+        for t_delta in [1e-3f32, 4e-3f32, 2e-3f32, 3e-3f32] {
+            pt1.update_with_time(1f32, t_delta).unwrap();
+        }
+        assert!(float_cmp::approx_eq!(
+            f32,
+            pt1.output,
+            1f32 - (-1f32).exp(),
+            epsilon = 1e-5
+        ));
+
+        // slower filter from now on, without jump
+        pt1.set_time_constant(1f32).unwrap();
+        let output = pt1.output;
+        assert!(float_cmp::approx_eq!(
+            f32,
+            pt1.update(1f32),
+            output + (1f32 - output) * (1f32 - (-1e-3f32).exp()),
+            epsilon = 1e-6
+        ));
+    }
+
+    #[test]
+    fn complex() {
+        let cfg = PT1Config {
+            K_p: 1f32,
+            T: 0.01f32,
+            discretization: Discretization::ZeroOrderHold,
+        };
+        let mut pt1 = ComplexPT1::new(cfg, 1000f32).unwrap();
+
+        // both parts follow like a real PT1 each
+        let mut output = c32(0f32, 0f32);
+        for _ in 0..10 {
+            output = pt1.update(c32(1f32, -2f32));
+        }
+        let step = 1f32 - (-1f32).exp();
+        assert!(float_cmp::approx_eq!(f32, output.re, step, epsilon = 1e-5));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            output.im,
+            -2f32 * step,
+            epsilon = 1e-5
+        ));
+
+        pt1.reset(c32(3f32, 4f32));
+        assert_eq!(pt1.update(c32(3f32, 4f32)), c32(3f32, 4f32));
+    }
+
+    #[test]
+    fn synchronous() {
+        use core::f32::consts::PI;
+
+        let f_sample = 10000f32;
+        let omega = 2f32 * PI * 50f32;
+        let cfg = || PT1Config {
+            K_p: 1f32,
+            T: 0.01f32,
+            discretization: Discretization::Tustin,
+        };
+        let mut sync = SynchronousPT1::new(cfg(), f_sample).unwrap();
+        let mut plain = ComplexPT1::new(cfg(), f_sample).unwrap();
+
+        // fundamental with a fifth harmonic, spinning the other way like in a motor
+        let (mut error_sync, mut error_plain) = (0f32, 0f32);
+        for n in 0..4000 {
+            let angle = omega * n as f32 / f_sample;
+            let fundamental = dq2ab(c32(1f32, 0f32), angle);
+            let input = fundamental + dq2ab(c32(0.2f32, 0f32), -5f32 * angle);
+            let output_sync = sync.update(input, angle);
+            let output_plain = plain.update(input);
+            if n > 3000 {
+                let distance = |x: Complex<f32>| (x - fundamental).norm_sqr().sqrt();
+                error_sync = error_sync.max(distance(output_sync));
+                error_plain = error_plain.max(distance(output_plain));
+            }
+        }
+        // fundamental passes in phase, harmonic is damped from 0.2
+        assert!(error_sync < 0.04f32);
+        // plain filter lags and attenuates the fundamental
+        assert!(error_plain > 0.5f32);
+    }
+
+    #[test]
+    fn invalid_config() {
+        let cfg = |T: f32| PT1Config {
+            K_p: 1f32,
+            T,
+            discretization: Discretization::ForwardEuler,
+        };
+        for T in [0f32, -1f32, f32::NAN, f32::INFINITY] {
+            assert_eq!(
+                PT1::new(cfg(T), 1000f32).err(),
+                Some(Error::InvalidTimeConstant)
+            );
+        }
+        for f_sample in [0f32, -1000f32, f32::INFINITY] {
+            assert_eq!(
+                ComplexPT1::new(cfg(1f32), f_sample).err(),
+                Some(Error::InvalidSampleTime)
+            );
+        }
+
+        // refused changes leave the filter as it is
+        let mut pt1 = PT1::new(cfg(1f32), 1000f32).unwrap();
+        assert_eq!(pt1.update(1f32), 1e-3f32);
+        assert_eq!(pt1.set_time_constant(0f32), Err(Error::InvalidTimeConstant));
+        assert_eq!(
+            pt1.update_with_time(1f32, -1e-3f32),
+            Err(Error::InvalidSampleTime)
+        );
+        assert_eq!(pt1.update(1f32), 1e-3f32 + 0.999f32 * 1e-3f32);
+    }
 }
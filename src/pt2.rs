@@ -0,0 +1,175 @@
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+#![allow(non_snake_case)]
+
+//! second order delay
+//!
+//! A PT2 is what you get when something with mass hangs on a spring with some friction: a low pass
+//! of second order, K / (1 + 2 D T s + T² s²), that overshoots for a damping D below 1 and creeps
+//! for a damping above. Use it to filter, or to model your plant in tests.
+//!
+//! It's discretized with the tustin transformation and runs as a [Biquad].
+
+use crate::biquad::{Biquad, Coefficients};
+
+/// PT2 errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// time constant is not positive and finite
+    InvalidTimeConstant,
+    /// damping is negative or not finite
+    InvalidDamping,
+    /// sampling frequency is not positive and finite
+    InvalidSampleTime,
+}
+
+/// PT2 state
+pub struct PT2 {
+    /// discretized filter
+    biquad: Biquad,
+    /// most recent output
+    output: f32,
+    /// configuration
+    config: PT2Config,
+}
+
+/// configuration structure for PT2
+pub struct PT2Config {
+    /// amplification
+    pub K_p: f32,
+    /// time constant, the inverse of the natural frequency
+    pub T: f32,
+    /// damping, 1 for the fastest response without overshoot
+    pub damping: f32,
+}
+
+impl PT2 {
+    /// create new PT2
+    pub fn new(cfg: PT2Config, f_sample_Hz: f32) -> Result<PT2, Error> {
+        if !cfg.T.is_finite() || cfg.T <= 0f32 {
+            return Err(Error::InvalidTimeConstant);
+        }
+        if !cfg.damping.is_finite() || cfg.damping < 0f32 {
+            return Err(Error::InvalidDamping);
+        }
+        if !f_sample_Hz.is_finite() || f_sample_Hz <= 0f32 {
+            return Err(Error::InvalidSampleTime);
+        }
+
+        // s = c (1 - z⁻¹) / (1 + z⁻¹)
+        let c = 2f32 * f_sample_Hz;
+        let w = 1f32 / cfg.T;
+        let a0 = c * c + 2f32 * cfg.damping * w * c + w * w;
+        let a1 = (2f32 * w * w - 2f32 * c * c) / a0;
+        let a2 = (c * c - 2f32 * cfg.damping * w * c + w * w) / a0;
+        // K ω² / a0 in theory, but for time constants of many samples the rounded a1 and a2 would
+        // miss the gain K noticeably. So take the gain from them.
+        let b = cfg.K_p * (1f32 + a1 + a2) / 4f32;
+        let coefficients = Coefficients {
+            b0: b,
+            b1: 2f32 * b,
+            b2: b,
+            a1,
+            a2,
+        };
+
+        Ok(PT2 {
+            biquad: Biquad::new(coefficients),
+            output: 0f32,
+            config: cfg,
+        })
+    }
+
+    /// update filter with new data
+    pub fn update(&mut self, input: f32) -> f32 {
+        self.output = self.biquad.update(input);
+
+        self.output
+    }
+
+    /// reset filter to given steady state output. if in doubt, use 0
+    pub fn reset(&mut self, reset_value: f32) {
+        let input = if self.config.K_p != 0f32 {
+            reset_value / self.config.K_p
+        } else {
+            0f32
+        };
+        self.biquad.reset(input);
+        self.output = reset_value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f32::consts::PI;
+
+    #[test]
+    fn pt2() {
+        let cfg = PT2Config {
+            K_p: 2f32,
+            T: 0.01f32,
+            damping: 0.5f32,
+        };
+        let mut pt2 = PT2::new(cfg, 10000f32).unwrap();
+
+        let mut peak = (0f32, 0usize);
+        for n in 0..2000 {
+            let output = pt2.update(1f32);
+            if output > peak.0 {
+                peak = (output, n);
+            }
+        }
+
+        // settled on K
+        assert!(float_cmp::approx_eq!(f32, pt2.output, 2f32, epsilon = 1e-3));
+        // overshoot of exp(-π D / √(1 - D²)) at π T / √(1 - D²)
+        let root = (1f32 - 0.25f32).sqrt();
+        assert!(float_cmp::approx_eq!(
+            f32,
+            peak.0,
+            2f32 * (1f32 + (-PI * 0.5f32 / root).exp()),
+            epsilon = 1e-2
+        ));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            peak.1 as f32 / 10000f32,
+            PI * 0.01f32 / root,
+            epsilon = 1e-3
+        ));
+
+        // reset to steady state
+        pt2.reset(3f32);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            pt2.update(1.5f32),
+            3f32,
+            epsilon = 1e-5
+        ));
+    }
+
+    #[test]
+    fn invalid_config() {
+        let cfg = |T: f32, damping: f32| PT2Config {
+            K_p: 1f32,
+            T,
+            damping,
+        };
+        for T in [0f32, -0.01f32, f32::NAN] {
+            assert_eq!(
+                PT2::new(cfg(T, 0.5f32), 10000f32).err(),
+                Some(Error::InvalidTimeConstant)
+            );
+        }
+        for damping in [-0.5f32, f32::NAN] {
+            assert_eq!(
+                PT2::new(cfg(0.01f32, damping), 10000f32).err(),
+                Some(Error::InvalidDamping)
+            );
+        }
+        assert_eq!(
+            PT2::new(cfg(0.01f32, 0.5f32), 0f32).err(),
+            Some(Error::InvalidSampleTime)
+        );
+    }
+}
@@ -0,0 +1,300 @@
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+#![allow(non_snake_case)]
+
+//! setpoint ramps
+//!
+//! Setpoints tend to arrive as steps. Feed a step into a speed controller and it asks for all the
+//! current it's allowed to, the mechanics get a kick and the integrator winds up. Shape the
+//! setpoint first.
+//!
+//! [RateLimiter] is the simple way: the output follows the setpoint, but never faster than a
+//! rising or falling rate. The result is a ramp with kinks at both ends.
+//!
+//! The kinks are steps in acceleration, and thus in torque. [SCurve] smoothes them out by limiting
+//! the jerk, the change of acceleration, as well. Speed then follows an S: acceleration ramps up,
+//! stays at its limit, and ramps down again just in time to arrive at the setpoint without
+//! overshoot. It starts braking once
+//!
+//! |v_set - v| ≤ a² / 2j
+//!
+//! which is the speed change left while acceleration ramps down to zero with the jerk j. If the
+//! setpoint changes during a ramp, it bends over to the new one from wherever it is, without steps
+//! in acceleration.
+//!
+//! It hands out speed, acceleration and the angle integrated from speed as [Mechanical], so it
+//! drops in where an estimator would, e.g. as feedforward for the speed controller.
+
+use crate::motor::{wrap_angle, Mechanical};
+#[cfg_attr(test, allow(unused_imports))]
+use num_traits::Float;
+
+/// ramp errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// a limit or the sampling frequency is not positive and finite
+    InvalidLimit,
+}
+
+/// configuration struct for rate limiter construction
+#[derive(PartialEq, Debug)]
+pub struct RateLimiterConfig {
+    /// highest rate per second the output rises with
+    pub rate_rising: f32,
+    /// highest rate per second the output falls with
+    pub rate_falling: f32,
+}
+
+/// rate limiter state
+#[derive(PartialEq, Debug)]
+pub struct RateLimiter {
+    /// most recent output
+    output: f32,
+    /// highest rise per sample
+    step_rising: f32,
+    /// highest fall per sample
+    step_falling: f32,
+}
+
+impl RateLimiter {
+    /// create new rate limiter, starting at 0
+    pub fn new(cfg: RateLimiterConfig, f_sample_Hz: f32) -> Result<RateLimiter, Error> {
+        check_positive(&[cfg.rate_rising, cfg.rate_falling, f_sample_Hz])?;
+        Ok(RateLimiter {
+            output: 0f32,
+            step_rising: cfg.rate_rising / f_sample_Hz,
+            step_falling: cfg.rate_falling / f_sample_Hz,
+        })
+    }
+
+    /// run this method on every sample with the setpoint. Returns the limited setpoint.
+    pub fn update(&mut self, setpoint: f32) -> f32 {
+        self.output += (setpoint - self.output).clamp(-self.step_falling, self.step_rising);
+        self.output
+    }
+
+    /// most recent output
+    pub fn output(&self) -> f32 {
+        self.output
+    }
+
+    /// restart from a value, e.g. the measured speed when the controller takes over
+    pub fn reset(&mut self, value: f32) {
+        self.output = value;
+    }
+}
+
+/// configuration struct for S-curve construction
+#[derive(PartialEq, Debug)]
+pub struct SCurveConfig {
+    /// highest speed magnitude in rad per second. Setpoints beyond are clamped.
+    pub speed_max: f32,
+    /// highest acceleration magnitude in rad per second²
+    pub acceleration_max: f32,
+    /// highest jerk magnitude in rad per second³
+    pub jerk_max: f32,
+}
+
+/// jerk limited speed trajectory generator
+#[derive(PartialEq, Debug)]
+pub struct SCurve {
+    /// angle integrated from speed
+    angle: f32,
+    /// speed
+    speed: f32,
+    /// acceleration
+    acceleration: f32,
+    /// sample time
+    t_sample: f32,
+    /// configuration
+    config: SCurveConfig,
+}
+
+impl SCurve {
+    /// create new S-curve generator, starting at standstill
+    pub fn new(cfg: SCurveConfig, f_sample_Hz: f32) -> Result<SCurve, Error> {
+        check_positive(&[
+            cfg.speed_max,
+            cfg.acceleration_max,
+            cfg.jerk_max,
+            f_sample_Hz,
+        ])?;
+        Ok(SCurve {
+            angle: 0f32,
+            speed: 0f32,
+            acceleration: 0f32,
+            t_sample: 1f32 / f_sample_Hz,
+            config: cfg,
+        })
+    }
+
+    /// run this method on every sample with the speed setpoint in rad per second. Returns the
+    /// trajectory sample.
+    pub fn update(&mut self, speed_setpoint: f32) -> Mechanical {
+        let cfg = &self.config;
+        let jerk_step = cfg.jerk_max * self.t_sample;
+        let target = speed_setpoint.clamp(-cfg.speed_max, cfg.speed_max);
+        let error = target - self.speed;
+
+        // acceleration that ramps down to zero with full jerk right on the target. Ramping down
+        // from a in steps of jerk_step adds a (a + jerk_step) / 2j to the speed.
+        let acceleration_set = error.signum()
+            * (((jerk_step * jerk_step + 8f32 * cfg.jerk_max * error.abs()).sqrt() - jerk_step)
+                / 2f32)
+                .min(cfg.acceleration_max);
+        self.acceleration += (acceleration_set - self.acceleration).clamp(-jerk_step, jerk_step);
+        self.speed += self.acceleration * self.t_sample;
+
+        // arrived, if acceleration is small enough to stop within one jerk step
+        let passed = (target - self.speed) * error <= 0f32;
+        if passed && self.acceleration.abs() <= jerk_step {
+            self.speed = target;
+            self.acceleration = 0f32;
+        }
+
+        self.angle = wrap_angle(self.angle + self.speed * self.t_sample);
+        self.state()
+    }
+
+    /// most recent trajectory sample
+    pub fn state(&self) -> Mechanical {
+        Mechanical {
+            angle: self.angle,
+            speed: self.speed,
+            acceleration: self.acceleration,
+        }
+    }
+
+    /// restart from a speed at rest in acceleration, e.g. the measured speed when the controller
+    /// takes over
+    pub fn reset(&mut self, angle: f32, speed: f32) {
+        self.angle = wrap_angle(angle);
+        self.speed = speed;
+        self.acceleration = 0f32;
+    }
+}
+
+/// check limits for positive finite values
+fn check_positive(values: &[f32]) -> Result<(), Error> {
+    if values
+        .iter()
+        .all(|value| value.is_finite() && *value > 0f32)
+    {
+        Ok(())
+    } else {
+        Err(Error::InvalidLimit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const F_SAMPLE: f32 = 1000f32;
+
+    fn config() -> SCurveConfig {
+        SCurveConfig {
+            speed_max: 100f32,
+            acceleration_max: 200f32,
+            jerk_max: 2000f32,
+        }
+    }
+
+    #[test]
+    fn rate_limiter() {
+        let cfg = RateLimiterConfig {
+            rate_rising: 100f32,
+            rate_falling: 50f32,
+        };
+        let mut ramp = RateLimiter::new(cfg, F_SAMPLE).unwrap();
+
+        // 0.1 per sample up, 0.05 per sample down
+        for _ in 0..50 {
+            ramp.update(10f32);
+        }
+        assert!(float_cmp::approx_eq!(
+            f32,
+            ramp.output(),
+            5f32,
+            epsilon = 1e-4
+        ));
+        for _ in 0..20 {
+            ramp.update(-10f32);
+        }
+        assert!(float_cmp::approx_eq!(
+            f32,
+            ramp.output(),
+            4f32,
+            epsilon = 1e-4
+        ));
+        // arrives exactly
+        for _ in 0..1000 {
+            ramp.update(3f32);
+        }
+        assert_eq!(ramp.output(), 3f32);
+
+        let cfg = RateLimiterConfig {
+            rate_rising: 0f32,
+            rate_falling: 50f32,
+        };
+        assert_eq!(RateLimiter::new(cfg, F_SAMPLE), Err(Error::InvalidLimit));
+    }
+
+    /// run for a number of samples, checking the limits on the way
+    fn run(scurve: &mut SCurve, setpoint: f32, samples: usize) {
+        let cfg = config();
+        let jerk_step = cfg.jerk_max / F_SAMPLE;
+        for _ in 0..samples {
+            let acceleration = scurve.acceleration;
+            let state = scurve.update(setpoint);
+            // arriving may round up a little
+            assert!((state.acceleration - acceleration).abs() <= jerk_step * 1.01f32);
+            assert!(state.acceleration.abs() <= cfg.acceleration_max);
+            assert!(state.speed.abs() <= cfg.speed_max);
+        }
+    }
+
+    #[test]
+    fn s_curve() {
+        let mut scurve = SCurve::new(config(), F_SAMPLE).unwrap();
+
+        // 0.1 s jerk up, 0.4 s at full acceleration, 0.1 s jerk down: 0.6 s to 100 rad/s
+        run(&mut scurve, 100f32, 590);
+        assert!(scurve.state().speed < 100f32);
+        run(&mut scurve, 100f32, 20);
+        assert_eq!(scurve.state().speed, 100f32);
+        assert_eq!(scurve.state().acceleration, 0f32);
+
+        // setpoint beyond the limit is clamped
+        run(&mut scurve, 150f32, 100);
+        assert_eq!(scurve.state().speed, 100f32);
+    }
+
+    #[test]
+    fn setpoint_change() {
+        let mut scurve = SCurve::new(config(), F_SAMPLE).unwrap();
+
+        // turn around in the middle of a ramp
+        run(&mut scurve, 100f32, 300);
+        let speed = scurve.state().speed;
+        let mut speed_max = speed;
+        for _ in 0..1000 {
+            run(&mut scurve, -20f32, 1);
+            speed_max = speed_max.max(scurve.state().speed);
+        }
+        // can't stop accelerating at once, but bends over smoothly
+        assert!(speed_max > speed);
+        assert!(speed_max < speed + 11f32);
+        assert_eq!(scurve.state().speed, -20f32);
+
+        // angle follows speed
+        scurve.reset(0f32, 10f32);
+        run(&mut scurve, 10f32, 100);
+        assert!(float_cmp::approx_eq!(
+            f32,
+            scurve.state().angle,
+            1f32,
+            epsilon = 1e-4
+        ));
+    }
+}
@@ -0,0 +1,247 @@
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+#![allow(non_snake_case)]
+
+//! resolver and sin/cos encoder rotor estimator
+//!
+//! Resolvers and sin/cos encoders hand us two analog signals: the sine and the cosine of the rotor
+//! angle. A resolver modulates them onto an excitation carrier, so make sure to demodulate first and
+//! feed in the envelopes.
+//!
+//! The obvious way of getting the angle back is atan2. It's also the noisiest one and gives no speed.
+//! Instead we run an angle tracking loop. We keep an estimated angle θ' and calculate
+//!
+//! sin(θ) cos(θ') - cos(θ) sin(θ') = sin(θ - θ')
+//!
+//! which for small errors is just the angle error. A PI controller turns it into speed, which is
+//! integrated into θ'. Two integrators in the loop make it a type II loop, so it follows a constant
+//! speed without any angle error.
+//!
+//! Real signals aren't perfect sine and cosine. They come with offsets, different amplitudes and
+//! their phase shift isn't exactly 90°. These get corrected before the loop sees them. If the
+//! corrected amplitude leaves its expected range, a wire broke or the sensor is gone.
+
+use crate::motor::{wrap_angle, Mechanical};
+use crate::pid::{self, PIDConfig, PID};
+#[cfg_attr(test, allow(unused_imports))]
+use num_traits::Float;
+
+/// resolver errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// amplitude of the sin/cos signals is out of range
+    SignalLoss,
+    /// bandwidth or damping of the tracking loop is not positive and finite
+    InvalidLoop,
+    /// speed limit or sampling frequency don't make a valid PI controller
+    InvalidConfig(pid::Error),
+}
+
+/// configuration struct for resolver construction
+#[derive(PartialEq, Debug)]
+pub struct ResolverConfig {
+    /// natural frequency of the tracking loop in rad per second
+    pub bandwidth: f32,
+    /// damping of the tracking loop, 1 for critical damping
+    pub damping: f32,
+    /// maximum tracked speed in rad per second
+    pub speed_max: f32,
+    /// offset of the sine signal
+    pub sin_offset: f32,
+    /// offset of the cosine signal
+    pub cos_offset: f32,
+    /// amplitude of the sine signal
+    pub sin_amplitude: f32,
+    /// amplitude of the cosine signal
+    pub cos_amplitude: f32,
+    /// phase error of the cosine signal in rad. The cosine is expected as cos(θ + phase_error).
+    pub phase_error: f32,
+    /// minimal corrected amplitude, 1 is nominal
+    pub amplitude_min: f32,
+    /// maximal corrected amplitude, 1 is nominal
+    pub amplitude_max: f32,
+}
+
+/// resolver angle tracking loop
+#[derive(PartialEq, Debug)]
+pub struct Resolver {
+    /// estimated angle
+    angle: f32,
+    /// most recent speed for acceleration calculation
+    speed_recent: f32,
+    /// PI controller of the tracking loop
+    pi: PID,
+    /// sample time
+    t_sample: f32,
+    /// configuration
+    config: ResolverConfig,
+}
+
+impl Resolver {
+    /// create new resolver tracking loop
+    pub fn new(cfg: ResolverConfig, f_sample_Hz: f32) -> Result<Resolver, Error> {
+        let positive = |value: f32| value.is_finite() && value > 0f32;
+        if !positive(cfg.bandwidth) || !positive(cfg.damping) {
+            return Err(Error::InvalidLoop);
+        }
+        // speed = K_p * (e + K_i * ∫e) with K_p = 2 ζ ω_n and K_p * K_i = ω_n²
+        let pi_cfg = PIDConfig::new(
+            2f32 * cfg.damping * cfg.bandwidth,
+            cfg.bandwidth / (2f32 * cfg.damping),
+            0f32,
+            (-cfg.speed_max, cfg.speed_max),
+        )
+        .map_err(Error::InvalidConfig)?;
+        let pi = PID::try_new(pi_cfg, f_sample_Hz).map_err(Error::InvalidConfig)?;
+        Ok(Resolver {
+            angle: 0f32,
+            speed_recent: 0f32,
+            pi,
+            t_sample: 1f32 / f_sample_Hz,
+            config: cfg,
+        })
+    }
+
+    /// run this method on every sample with the demodulated sine and cosine signals. Returns the
+    /// tracked rotor state or an error if the signals are lost.
+    pub fn update(&mut self, sin: f32, cos: f32) -> Result<Mechanical, Error> {
+        // correct offset and amplitude
+        let sin_c = (sin - self.config.sin_offset) / self.config.sin_amplitude;
+        let cos_m = (cos - self.config.cos_offset) / self.config.cos_amplitude;
+        // correct phase: cos(θ + φ) = cos θ cos φ - sin θ sin φ
+        let cos_c = (cos_m + sin_c * self.config.phase_error.sin()) / self.config.phase_error.cos();
+
+        let amplitude = (sin_c * sin_c + cos_c * cos_c).sqrt();
+        if amplitude < self.config.amplitude_min || amplitude > self.config.amplitude_max {
+            return Err(Error::SignalLoss);
+        }
+
+        // predict angle for this sample
+        self.angle = wrap_angle(self.angle + self.speed_recent * self.t_sample);
+
+        // angle error sin(θ - θ'), normalized by amplitude
+        let error = (sin_c * self.angle.cos() - cos_c * self.angle.sin()) / amplitude;
+        let speed = self.pi.update(error);
+
+        let acceleration = (speed - self.speed_recent) / self.t_sample;
+        self.speed_recent = speed;
+
+        Ok(Mechanical {
+            angle: self.angle,
+            speed,
+            acceleration,
+        })
+    }
+
+    /// set tracked angle, e.g. from atan2 of the first samples, to skip the initial lock in
+    pub fn reset(&mut self, angle: f32) {
+        self.angle = angle;
+        self.speed_recent = 0f32;
+        self.pi.reset(0f32, 0f32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::motor::wrap_angle_diff;
+
+    const F_SAMPLE: f32 = 10000f32;
+
+    fn config() -> ResolverConfig {
+        ResolverConfig {
+            bandwidth: 500f32,
+            damping: 1f32,
+            speed_max: 5000f32,
+            sin_offset: 0.1f32,
+            cos_offset: -0.05f32,
+            sin_amplitude: 1.2f32,
+            cos_amplitude: 0.9f32,
+            phase_error: 0.05f32,
+            amplitude_min: 0.5f32,
+            amplitude_max: 1.5f32,
+        }
+    }
+
+    #[test]
+    fn tracking() {
+        let cfg = config();
+        let (sin_off, cos_off, sin_amp, cos_amp, phase) = (
+            cfg.sin_offset,
+            cfg.cos_offset,
+            cfg.sin_amplitude,
+            cfg.cos_amplitude,
+            cfg.phase_error,
+        );
+        let mut resolver = Resolver::new(cfg, F_SAMPLE).unwrap();
+
+        // constant speed of 100 rad/s with imperfect signals
+        let speed = 100f32;
+        let mut angle = 1f32;
+        let mut state = resolver.update(0f32, 1f32).unwrap();
+        for _ in 0..2000 {
+            angle += speed / F_SAMPLE;
+            let sin = sin_amp * angle.sin() + sin_off;
+            let cos = cos_amp * (angle + phase).cos() + cos_off;
+            state = resolver.update(sin, cos).unwrap();
+        }
+
+        assert!(float_cmp::approx_eq!(
+            f32,
+            wrap_angle_diff(state.angle - angle),
+            0f32,
+            epsilon = 1e-3
+        ));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            state.speed,
+            speed,
+            epsilon = 0.1
+        ));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            state.acceleration,
+            0f32,
+            epsilon = 10f32
+        ));
+    }
+
+    #[test]
+    fn signal_loss() {
+        let cfg = config();
+        let (sin_off, cos_off) = (cfg.sin_offset, cfg.cos_offset);
+        let mut resolver = Resolver::new(cfg, F_SAMPLE).unwrap();
+
+        assert!(resolver.update(sin_off, 0.9f32 + cos_off).is_ok());
+        // both wires broken
+        assert_eq!(resolver.update(sin_off, cos_off), Err(Error::SignalLoss));
+        // sensor shorted to supply
+        assert_eq!(resolver.update(3f32, 3f32), Err(Error::SignalLoss));
+    }
+
+    #[test]
+    fn invalid_config() {
+        let cfg = ResolverConfig {
+            damping: 0f32,
+            ..config()
+        };
+        assert_eq!(Resolver::new(cfg, F_SAMPLE), Err(Error::InvalidLoop));
+        let cfg = ResolverConfig {
+            bandwidth: f32::NAN,
+            ..config()
+        };
+        assert_eq!(Resolver::new(cfg, F_SAMPLE), Err(Error::InvalidLoop));
+        let cfg = ResolverConfig {
+            speed_max: 0f32,
+            ..config()
+        };
+        assert_eq!(
+            Resolver::new(cfg, F_SAMPLE),
+            Err(Error::InvalidConfig(pid::Error::InvalidLimits))
+        );
+        assert_eq!(
+            Resolver::new(config(), 0f32),
+            Err(Error::InvalidConfig(pid::Error::InvalidSampleRate))
+        );
+    }
+}
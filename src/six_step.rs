@@ -0,0 +1,252 @@
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+
+//! six-step (block, trapezoidal) commutation for BLDC motors
+//!
+//! If you don't need the smoothness of field oriented control, six-step commutation is the cheap
+//! way of getting a motor to turn. In every one of the 6 rotor sectors, one phase is switched to
+//! the positive rail, one to the negative rail and the third one is left floating. Torque is
+//! controlled by the duty cycle of the PWM on the active phases.
+//!
+//! The sector numbering is the one of [crate::hall]. A current flowing from phase a to phase b
+//! creates a stator field at 330°, so to pull the rotor ahead we always energise the phase pair
+//! whose field lies 90° ahead of the sector center (or behind, for the other direction).
+//!
+//! | rotor sector | clockwise   | counterclockwise |
+//! | ------------ | ----------- | ---------------- |
+//! | 0            | b+ c-, a -- | c+ b-, a --      |
+//! | 1            | b+ a-, c -- | a+ b-, c --      |
+//! | 2            | c+ a-, b -- | a+ c-, b --      |
+//! | 3            | c+ b-, a -- | b+ c-, a --      |
+//! | 4            | a+ b-, c -- | b+ a-, c --      |
+//! | 5            | a+ c-, b -- | c+ a-, b --      |
+//!
+//! Since the stator field jumps by 60° at every commutation, an advance angle can only be applied
+//! with a finer rotor angle than the bare sector. Interpolate the angle between hall edges with the
+//! speed [crate::hall::Hall] hands you and use [SixStep::commutate_angle].
+
+use crate::motor::Mechanical;
+use core::f32::consts::PI;
+#[cfg_attr(test, allow(unused_imports))]
+use num_traits::Float;
+
+/// six-step commutation errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// sector number is not within 0 to 5
+    InvalidSector,
+}
+
+/// switching state of a single inverter half bridge
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum PhaseState {
+    /// high side switch is pulse width modulated, low side is off
+    High,
+    /// low side switch is on, high side is off
+    Low,
+    /// both switches are off, the phase floats
+    Floating,
+}
+
+/// rotational direction, named like the sector sequence in [crate::hall]
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Direction {
+    /// increasing sector numbers
+    Clockwise,
+    /// decreasing sector numbers
+    CounterClockwise,
+}
+
+/// configuration of six-step commutation
+#[derive(PartialEq, Debug)]
+pub struct SixStepConfig {
+    /// commutation advance in electrical rad. Compensates for the current rise time at high
+    /// speeds. 0 commutates right on the sector border.
+    pub advance: f32,
+    /// rotational direction to drive the motor in
+    pub direction: Direction,
+}
+
+/// phase pattern of a single commutation step, ready for the PWM peripheral
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Commutation {
+    /// switching state of phase a, b and c
+    pub phases: [PhaseState; 3],
+    /// duty cycle of the high side switch from 0 to 1
+    pub duty: f32,
+}
+
+impl Commutation {
+    /// duty cycle for every phase. Floating phases return None, so their driver should be
+    /// disabled, low phases get a duty cycle of 0.
+    pub fn pwm_duties(&self) -> [Option<f32>; 3] {
+        self.phases.map(|phase| match phase {
+            PhaseState::High => Some(self.duty),
+            PhaseState::Low => Some(0f32),
+            PhaseState::Floating => None,
+        })
+    }
+}
+
+/// phase states for the stator field vectors at 30° + n * 60°
+const FIELD_VECTOR_TO_PHASES: [[PhaseState; 3]; 6] = [
+    [PhaseState::High, PhaseState::Floating, PhaseState::Low],
+    [PhaseState::Floating, PhaseState::High, PhaseState::Low],
+    [PhaseState::Low, PhaseState::High, PhaseState::Floating],
+    [PhaseState::Low, PhaseState::Floating, PhaseState::High],
+    [PhaseState::Floating, PhaseState::Low, PhaseState::High],
+    [PhaseState::High, PhaseState::Low, PhaseState::Floating],
+];
+
+/// six-step commutation state
+#[derive(PartialEq, Debug)]
+pub struct SixStep {
+    /// duty cycle applied to the high side
+    duty: f32,
+    /// commutation configuration
+    config: SixStepConfig,
+}
+
+impl SixStep {
+    /// create new six-step commutation with 0 duty cycle
+    pub fn new(cfg: SixStepConfig) -> SixStep {
+        SixStep {
+            duty: 0f32,
+            config: cfg,
+        }
+    }
+
+    /// set duty cycle of the active high side. Values are limited to 0 to 1.
+    pub fn set_duty(&mut self, duty: f32) {
+        self.duty = duty.clamp(0f32, 1f32);
+    }
+
+    /// change rotational direction
+    pub fn set_direction(&mut self, direction: Direction) {
+        self.config.direction = direction;
+    }
+
+    /// commutate by a rotor sector from 0 to 5, e.g. from [crate::hall::Hall::sector]
+    pub fn commutate_sector(&self, sector: i8) -> Result<Commutation, Error> {
+        if !(0..6).contains(&sector) {
+            return Err(Error::InvalidSector);
+        }
+        let vector = match self.config.direction {
+            Direction::Clockwise => (sector + 1) % 6,
+            Direction::CounterClockwise => (sector + 4) % 6,
+        };
+        Ok(Commutation {
+            phases: FIELD_VECTOR_TO_PHASES[vector as usize],
+            duty: self.duty,
+        })
+    }
+
+    /// commutate by electrical rotor angle in rad. The configured advance is added in direction
+    /// of rotation before the sector is determined.
+    pub fn commutate_angle(&self, angle: f32) -> Commutation {
+        let advance = match self.config.direction {
+            Direction::Clockwise => self.config.advance,
+            Direction::CounterClockwise => -self.config.advance,
+        };
+        let sector = angle_to_sector(angle + advance);
+        // sector is always within 0 to 5
+        self.commutate_sector(sector).unwrap()
+    }
+
+    /// commutate by the mechanical state of the rotor. Its angle has to be electrical.
+    pub fn commutate_state(&self, state: &Mechanical) -> Commutation {
+        self.commutate_angle(state.angle)
+    }
+}
+
+/// sector number from 0 to 5 of any electrical angle. Sector 0 is centered around 0°.
+pub fn angle_to_sector(angle: f32) -> i8 {
+    let sector = ((angle + PI / 6f32) / (PI / 3f32)).floor() as i32;
+    sector.rem_euclid(6) as i8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sector_to_phases_cw() {
+        let mut six_step = SixStep::new(SixStepConfig {
+            advance: 0f32,
+            direction: Direction::Clockwise,
+        });
+        six_step.set_duty(0.5f32);
+
+        let res = six_step.commutate_sector(0).unwrap();
+        assert_eq!(
+            res.phases,
+            [PhaseState::Floating, PhaseState::High, PhaseState::Low]
+        );
+        assert_eq!(res.duty, 0.5f32);
+        assert_eq!(res.pwm_duties(), [None, Some(0.5f32), Some(0f32)]);
+
+        let res = six_step.commutate_sector(5).unwrap();
+        assert_eq!(
+            res.phases,
+            [PhaseState::High, PhaseState::Floating, PhaseState::Low]
+        );
+
+        assert_eq!(six_step.commutate_sector(6), Err(Error::InvalidSector));
+        assert_eq!(six_step.commutate_sector(-1), Err(Error::InvalidSector));
+    }
+
+    #[test]
+    fn sector_to_phases_ccw() {
+        let mut six_step = SixStep::new(SixStepConfig {
+            advance: 0f32,
+            direction: Direction::CounterClockwise,
+        });
+        // check duty limits
+        six_step.set_duty(2f32);
+
+        let res = six_step.commutate_sector(0).unwrap();
+        assert_eq!(
+            res.phases,
+            [PhaseState::Floating, PhaseState::Low, PhaseState::High]
+        );
+        assert_eq!(res.duty, 1f32);
+
+        let res = six_step.commutate_sector(3).unwrap();
+        assert_eq!(
+            res.phases,
+            [PhaseState::Floating, PhaseState::High, PhaseState::Low]
+        );
+    }
+
+    #[test]
+    fn advance_angle() {
+        let six_step = SixStep::new(SixStepConfig {
+            advance: 20f32 * PI / 180f32,
+            direction: Direction::Clockwise,
+        });
+
+        // 20° before the sector 1 border we already commutate into sector 1
+        let res = six_step.commutate_angle(15f32 * PI / 180f32);
+        assert_eq!(res.phases, six_step.commutate_sector(1).unwrap().phases);
+        let res = six_step.commutate_angle(5f32 * PI / 180f32);
+        assert_eq!(res.phases, six_step.commutate_sector(0).unwrap().phases);
+        // wrap around
+        let res = six_step.commutate_angle(-20f32 * PI / 180f32);
+        assert_eq!(res.phases, six_step.commutate_sector(0).unwrap().phases);
+    }
+
+    #[test]
+    fn hall_sector() {
+        let six_step = SixStep::new(SixStepConfig {
+            advance: 0f32,
+            direction: Direction::Clockwise,
+        });
+        let hall = crate::hall::Hall::new(false, true, true).unwrap();
+        assert_eq!(hall.sector(), 3);
+        let res = six_step.commutate_sector(hall.sector()).unwrap();
+        assert_eq!(
+            res.phases,
+            [PhaseState::Floating, PhaseState::Low, PhaseState::High]
+        );
+    }
+}
@@ -0,0 +1,253 @@
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+#![allow(non_snake_case)]
+
+//! controller gains from motor parameters
+//!
+//! Tuning by hand is fine for the weekend, but for the current and speed loops of a motor there's
+//! not much to guess once you know its parameters.
+//!
+//! The current loop plant is an RL circuit. Cancel its pole with the integrator and pick the
+//! proportional gain for the bandwidth you want:
+//!
+//! K_p = L ω_b, K_i = R / L
+//!
+//! K_i is given for [crate::pid::PID], which multiplies the integrator with K_p as well. In the
+//! usual parallel form it's R ω_b.
+//!
+//! The speed loop plant is the inertia, an integrator, behind the current loop, which looks like a
+//! small delay T_σ from up here. That's the textbook case for the symmetric optimum:
+//!
+//! K_p = J / (a K_t T_σ), T_n = a² T_σ
+//!
+//! with a = 2ζ + 1 for a damping ζ. a = 2 is the classic choice with some overshoot, larger values
+//! are slower and calmer.
+//!
+//! A loop can't be faster than its sampling allows. If the bandwidth is more than a fifth of the
+//! sample rate in rad per second, the gains still come out, but with a warning attached.
+
+use crate::motor::Config;
+use crate::pid::{self, PIDConfig};
+
+/// highest realistic bandwidth times sample time
+const BANDWIDTH_SAMPLE_RATIO_MAX: f32 = 0.2f32;
+
+/// tuning errors
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// a motor parameter, bandwidth, damping or sampling frequency is not positive and finite
+    InvalidParameter,
+    /// resulting gains or limits are invalid
+    InvalidConfig(pid::Error),
+}
+
+/// tuning warnings
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Warning {
+    /// requested bandwidth is too high for the sample rate and will not be reached
+    BandwidthTooHigh {
+        /// highest realistic bandwidth in rad per second
+        bandwidth_max: f32,
+    },
+}
+
+/// tuned gains with an optional warning
+#[derive(PartialEq, Debug)]
+pub struct Tuning<T> {
+    /// calculated gains
+    pub gains: T,
+    /// reason to distrust the gains
+    pub warning: Option<Warning>,
+}
+
+/// gains of the d and q current loops
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct CurrentLoopGains {
+    /// d axis current controller, current error in, voltage out
+    pub d: PIDConfig,
+    /// q axis current controller, current error in, voltage out
+    pub q: PIDConfig,
+}
+
+/// gains of the dq current loops from resistance and inductance of the motor for a bandwidth in
+/// rad per second. The output is limited to ±voltage_max.
+pub fn current_loop(
+    motor: &Config,
+    bandwidth: f32,
+    voltage_max: f32,
+    f_sample_Hz: f32,
+) -> Result<Tuning<CurrentLoopGains>, Error> {
+    let L = motor.inductance;
+    check_positive(&[
+        motor.resistance,
+        L.re,
+        L.im,
+        bandwidth,
+        voltage_max,
+        f_sample_Hz,
+    ])?;
+
+    let axis = |L: f32| {
+        PIDConfig::new(
+            L * bandwidth,
+            motor.resistance / L,
+            0f32,
+            (-voltage_max, voltage_max),
+        )
+        .map_err(Error::InvalidConfig)
+    };
+
+    Ok(Tuning {
+        gains: CurrentLoopGains {
+            d: axis(L.re)?,
+            q: axis(L.im)?,
+        },
+        warning: bandwidth_warning(bandwidth, f_sample_Hz),
+    })
+}
+
+/// speed loop gains by symmetric optimum, q current out and mechanical speed in rad per second in.
+/// The current loop is expected to run with current_bandwidth in rad per second at the same
+/// sample rate. Damping of 0.5 is the classic symmetric optimum. The output is limited to
+/// ±current_max.
+pub fn speed_loop(
+    motor: &Config,
+    pole_pairs: u8,
+    current_bandwidth: f32,
+    damping: f32,
+    current_max: f32,
+    f_sample_Hz: f32,
+) -> Result<Tuning<PIDConfig>, Error> {
+    check_positive(&[
+        motor.flux,
+        motor.inertia,
+        pole_pairs as f32,
+        current_bandwidth,
+        damping,
+        current_max,
+        f_sample_Hz,
+    ])?;
+
+    // torque per q current
+    let K_t = 1.5f32 * pole_pairs as f32 * motor.flux;
+    // closed current loop and sampling delay
+    let T_sigma = 1f32 / current_bandwidth + 1f32 / f_sample_Hz;
+    let a = 2f32 * damping + 1f32;
+
+    let gains = PIDConfig::new(
+        motor.inertia / (a * K_t * T_sigma),
+        1f32 / (a * a * T_sigma),
+        0f32,
+        (-current_max, current_max),
+    )
+    .map_err(Error::InvalidConfig)?;
+
+    // crossover of the speed loop, or the current loop if that one's out of reach already
+    let warning = bandwidth_warning(current_bandwidth, f_sample_Hz)
+        .or(bandwidth_warning(1f32 / (a * T_sigma), f_sample_Hz));
+
+    Ok(Tuning { gains, warning })
+}
+
+/// warn if bandwidth can't be reached at the sample rate
+fn bandwidth_warning(bandwidth: f32, f_sample_Hz: f32) -> Option<Warning> {
+    let bandwidth_max = BANDWIDTH_SAMPLE_RATIO_MAX * f_sample_Hz;
+    if bandwidth > bandwidth_max {
+        Some(Warning::BandwidthTooHigh { bandwidth_max })
+    } else {
+        None
+    }
+}
+
+/// check parameters for positive finite values
+fn check_positive(values: &[f32]) -> Result<(), Error> {
+    if values
+        .iter()
+        .all(|value| value.is_finite() && *value > 0f32)
+    {
+        Ok(())
+    } else {
+        Err(Error::InvalidParameter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pid::PID;
+    use num::complex::c32;
+
+    fn motor_config() -> Config {
+        Config {
+            resistance: 0.2f32,
+            inductance: c32(2e-4f32, 3e-4f32),
+            flux: 0.01f32,
+            inertia: 1e-4f32,
+        }
+    }
+
+    #[test]
+    fn current() {
+        let tuning = current_loop(&motor_config(), 1000f32, 24f32, 20000f32).unwrap();
+        assert_eq!(tuning.warning, None);
+        let d = tuning.gains.d;
+        assert!(float_cmp::approx_eq!(f32, d.K_p(), 0.2f32, epsilon = 1e-6));
+        assert!(float_cmp::approx_eq!(f32, d.K_i(), 1000f32, epsilon = 1e-2));
+        assert_eq!(d.limits(), (-24f32, 24f32));
+        let q = tuning.gains.q;
+        assert!(float_cmp::approx_eq!(f32, q.K_p(), 0.3f32, epsilon = 1e-6));
+
+        // a bandwidth close to the sample rate isn't going to happen
+        let tuning = current_loop(&motor_config(), 10000f32, 24f32, 20000f32).unwrap();
+        assert_eq!(
+            tuning.warning,
+            Some(Warning::BandwidthTooHigh {
+                bandwidth_max: 4000f32
+            })
+        );
+
+        assert_eq!(
+            current_loop(&motor_config(), 1000f32, 24f32, 0f32),
+            Err(Error::InvalidParameter)
+        );
+    }
+
+    #[test]
+    fn speed() {
+        let tuning = speed_loop(&motor_config(), 4, 2000f32, 0.5f32, 10f32, 10000f32).unwrap();
+        assert_eq!(tuning.warning, None);
+        let T_sigma = 1f32 / 2000f32 + 1f32 / 10000f32;
+        let K_t = 0.06f32;
+        assert!(float_cmp::approx_eq!(
+            f32,
+            tuning.gains.K_p(),
+            1e-4f32 / (2f32 * K_t * T_sigma),
+            epsilon = 1e-5
+        ));
+        assert!(float_cmp::approx_eq!(
+            f32,
+            tuning.gains.K_i(),
+            1f32 / (4f32 * T_sigma),
+            epsilon = 1e-2
+        ));
+
+        // closed loop with the current loop as first order lag settles on the setpoint
+        let mut pid = PID::try_new(tuning.gains, 10000f32).unwrap();
+        let (mut speed, mut current) = (0f32, 0f32);
+        let mut speed_max = 0f32;
+        for _ in 0..2000 {
+            let current_ref = pid.update(10f32 - speed);
+            current += (current_ref - current) * 2000f32 / 10000f32;
+            speed += K_t * current / 1e-4f32 / 10000f32;
+            speed_max = speed_max.max(speed);
+        }
+        assert!(float_cmp::approx_eq!(f32, speed, 10f32, epsilon = 0.05));
+        // symmetric optimum overshoots without setpoint filter, but stays stable
+        assert!(speed_max < 15f32);
+
+        assert_eq!(
+            speed_loop(&motor_config(), 0, 2000f32, 0.5f32, 10f32, 10000f32),
+            Err(Error::InvalidParameter)
+        );
+    }
+}